@@ -36,6 +36,7 @@
               mkdir -p $out/share/wichtel_loser
               cp -r templates $out/share/wichtel_loser/
               cp -r static $out/share/wichtel_loser/
+              cp -r locales $out/share/wichtel_loser/
             '';
             
             meta = with pkgs.lib; {
@@ -96,6 +97,18 @@
               default = "127.0.0.1";
               description = "Address to bind to";
             };
+
+            publicUrl = mkOption {
+              type = types.str;
+              example = "https://wichtel.example.org";
+              description = "Externally reachable base URL, put in front of the links in mails and QR codes";
+            };
+
+            sessionKeyFile = mkOption {
+              type = types.str;
+              example = "/run/secrets/wichtel-loser-session-key";
+              description = "File containing the base64-encoded session cookie key, read at runtime and never copied into the Nix store";
+            };
           };
           
           config = mkIf cfg.enable {
@@ -106,6 +119,11 @@
               
               environment = {
                 BIND_ADDRESS = "${cfg.address}:${toString cfg.port}";
+                PUBLIC_URL = cfg.publicUrl;
+                STORAGE_BACKEND = "sqlite";
+                DATABASE_PATH = "/var/lib/wichtel-loser/wichtel.db";
+                APP_ENV = "production";
+                SESSION_KEY_FILE = "%d/session-key";
               };
               
               serviceConfig = {
@@ -114,6 +132,8 @@
                 Restart = "always";
                 RestartSec = 5;
                 DynamicUser = true;
+                StateDirectory = "wichtel-loser";
+                LoadCredential = "session-key:${cfg.sessionKeyFile}";
               };
             };
           };
@@ -0,0 +1,373 @@
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::budget::Budget;
+use crate::draw::DrawMode;
+use crate::error::AppError;
+use crate::export;
+use crate::mailer::{self, Mailer};
+use crate::models::{EventStatus, Participant, WichtelEvent, Wishlist};
+use crate::rate_limit::RateLimiter;
+use crate::state::AppState;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(|err, _| {
+        AppError::BadRequest(err.to_string()).into()
+    }))
+    .service(create_event)
+    .service(import_event)
+    .service(get_event)
+    .service(export_event)
+    .service(join_event)
+    .service(list_participants)
+    .service(close_event)
+    .service(get_assignment)
+    .default_service(web::to(|| async {
+        Err::<HttpResponse, _>(AppError::NotFound("Unknown API endpoint"))
+    }));
+}
+
+type ApiResult = Result<HttpResponse, AppError>;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateEventRequest {
+    pub name: String,
+    #[serde(default)]
+    pub draw_mode: DrawMode,
+    #[serde(default)]
+    pub gifts_per_person: Option<usize>,
+    #[serde(default)]
+    pub exchange_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub registration_deadline: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub budget: Option<Budget>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JoinEventRequest {
+    pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct EventResponse<'a> {
+    id: Uuid,
+    name: &'a str,
+    invite_code: &'a str,
+    status: EventStatus,
+    draw_mode: DrawMode,
+    gifts_per_person: usize,
+    participant_count: usize,
+    exchange_date: Option<NaiveDate>,
+    registration_deadline: Option<DateTime<Utc>>,
+    budget: Option<&'a Budget>,
+    /// Published commitment of the upcoming or completed draw.
+    commitment: Option<&'a str>,
+    created_at: DateTime<Utc>,
+}
+
+impl<'a> From<&'a WichtelEvent> for EventResponse<'a> {
+    fn from(event: &'a WichtelEvent) -> Self {
+        Self {
+            id: event.id,
+            name: &event.name,
+            invite_code: &event.invite_code,
+            status: event.status,
+            draw_mode: event.draw_mode,
+            gifts_per_person: event.gifts_per_person,
+            participant_count: event.participants.len(),
+            exchange_date: event.exchange_date,
+            registration_deadline: event.registration_deadline,
+            budget: event.budget.as_ref(),
+            commitment: event
+                .draw_commitment
+                .as_ref()
+                .map(|c| c.commitment.as_str())
+                .or(event.draw_record.as_ref().map(|r| r.commitment.as_str())),
+            created_at: event.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedEventResponse<'a> {
+    #[serde(flatten)]
+    event: EventResponse<'a>,
+    organizer_token: Uuid,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ParticipantResponse<'a> {
+    id: Uuid,
+    name: &'a str,
+    joined_at: DateTime<Utc>,
+}
+
+impl<'a> From<&'a Participant> for ParticipantResponse<'a> {
+    fn from(participant: &'a Participant) -> Self {
+        Self {
+            id: participant.id,
+            name: &participant.name,
+            joined_at: participant.joined_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JoinedResponse<'a> {
+    event_id: Uuid,
+    #[serde(flatten)]
+    participant: ParticipantResponse<'a>,
+    pin: &'a str,
+    participant_token: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+struct AssignmentResponse<'a> {
+    event_id: Uuid,
+    status: EventStatus,
+    budget: Option<&'a Budget>,
+    participant: ParticipantResponse<'a>,
+    /// First of `recipients`, kept for clients written before multi-gift
+    /// events.
+    recipient: Option<RecipientResponse<'a>>,
+    recipients: Vec<RecipientResponse<'a>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RecipientResponse<'a> {
+    #[serde(flatten)]
+    participant: ParticipantResponse<'a>,
+    wishlist: &'a Wishlist,
+}
+
+fn bearer_token(req: &HttpRequest) -> Result<Uuid, AppError> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .and_then(|t| Uuid::parse_str(t.trim()).ok())
+        .ok_or(AppError::Unauthorized)
+}
+
+fn load_event(state: &AppState, event_id: &str) -> Result<WichtelEvent, AppError> {
+    Uuid::parse_str(event_id)
+        .ok()
+        .and_then(|id| state.get_event(&id))
+        .ok_or(AppError::NotFound("Event not found"))
+}
+
+fn load_organizer_event(
+    state: &AppState,
+    req: &HttpRequest,
+    event_id: &str,
+) -> Result<WichtelEvent, AppError> {
+    let token = bearer_token(req)?;
+    let event = load_event(state, event_id)?;
+    if event.organizer_token != token {
+        return Err(AppError::Forbidden("Token is not valid for this resource"));
+    }
+    Ok(event)
+}
+
+#[post("/events")]
+async fn create_event(
+    req: HttpRequest,
+    body: web::Json<CreateEventRequest>,
+    state: web::Data<AppState>,
+    limiter: web::Data<RateLimiter>,
+) -> ApiResult {
+    let body = body.into_inner();
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("Event name cannot be empty".to_string()));
+    }
+
+    if let Some(budget) = &body.budget {
+        budget
+            .validate()
+            .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    }
+
+    let mut event = WichtelEvent::new(name);
+    if let Some(gifts) = body.gifts_per_person {
+        event
+            .set_gifts_per_person(gifts)
+            .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    }
+    event
+        .set_registration_deadline(body.registration_deadline)
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    event.draw_mode = body.draw_mode;
+    event.exchange_date = body.exchange_date;
+    event.budget = body.budget;
+    limiter.check_new_event(&req)?;
+    let event = state.create_event(event).ok_or(AppError::Internal)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/api/v1/events/{}", event.id)))
+        .json(CreatedEventResponse {
+            event: EventResponse::from(&event),
+            organizer_token: event.organizer_token,
+        }))
+}
+
+#[get("/events/{event_id}")]
+async fn get_event(
+    path: web::Path<String>,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> ApiResult {
+    let event = load_organizer_event(&state, &req, &path)?;
+    Ok(HttpResponse::Ok().json(EventResponse::from(&event)))
+}
+
+#[get("/events/{event_id}/export")]
+async fn export_event(
+    path: web::Path<String>,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> ApiResult {
+    let event = load_organizer_event(&state, &req, &path)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(export::to_json(&event)?))
+}
+
+#[post("/events/import")]
+async fn import_event(
+    req: HttpRequest,
+    body: String,
+    state: web::Data<AppState>,
+    limiter: web::Data<RateLimiter>,
+) -> ApiResult {
+    let event = export::from_json(&body)?;
+    limiter.check_new_event(&req)?;
+    let event = state.import_event(event)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/api/v1/events/{}", event.id)))
+        .json(CreatedEventResponse {
+            event: EventResponse::from(&event),
+            organizer_token: event.organizer_token,
+        }))
+}
+
+#[post("/join/{invite_code}")]
+async fn join_event(
+    path: web::Path<String>,
+    body: web::Json<JoinEventRequest>,
+    state: web::Data<AppState>,
+    mailer: web::Data<Mailer>,
+) -> ApiResult {
+    let event = state
+        .get_event_by_invite_code(&path)
+        .ok_or(AppError::NotFound("Invalid invite code"))?;
+
+    if !event.accepts_participants() {
+        return Err(AppError::Conflict(
+            "This event is already closed for new participants".to_string(),
+        ));
+    }
+
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("Name cannot be empty".to_string()));
+    }
+
+    let email = mailer::parse_email(body.email.as_deref().unwrap_or_default())
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    let participant_id = state
+        .add_participant(&event.id, name, email)
+        .map_err(|e| match e {
+            "Event not found" => AppError::NotFound("Event not found"),
+            e => AppError::Conflict(e.to_string()),
+        })?;
+    let before = event;
+    let event = state.get_event(&before.id).ok_or(AppError::Internal)?;
+    let participant = event
+        .participants
+        .get(&participant_id)
+        .ok_or(AppError::Internal)?;
+    mailer.send_join_confirmation(&event, participant);
+    mailer.notify_changed_recipients(&before, &event);
+
+    Ok(HttpResponse::Created().json(JoinedResponse {
+        event_id: event.id,
+        participant: ParticipantResponse::from(participant),
+        pin: &participant.pin,
+        participant_token: participant.recovery_token,
+    }))
+}
+
+#[get("/events/{event_id}/participants")]
+async fn list_participants(
+    path: web::Path<String>,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> ApiResult {
+    let event = load_organizer_event(&state, &req, &path)?;
+
+    let mut participants: Vec<ParticipantResponse> = event
+        .participants
+        .values()
+        .map(ParticipantResponse::from)
+        .collect();
+    participants.sort_by_key(|p| p.joined_at);
+
+    Ok(HttpResponse::Ok().json(participants))
+}
+
+#[post("/events/{event_id}/close")]
+async fn close_event(
+    path: web::Path<String>,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    mailer: web::Data<Mailer>,
+) -> ApiResult {
+    let event = load_organizer_event(&state, &req, &path)?;
+
+    state.close_event(&event.id, &event.organizer_token)?;
+
+    let event = state.get_event(&event.id).ok_or(AppError::Internal)?;
+    mailer.send_draw_notifications(&event);
+    Ok(HttpResponse::Ok().json(EventResponse::from(&event)))
+}
+
+#[get("/events/{event_id}/assignment")]
+async fn get_assignment(
+    path: web::Path<String>,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> ApiResult {
+    let token = bearer_token(&req)?;
+    let event = load_event(&state, &path)?;
+    let participant = event
+        .participant_by_recovery_token(&token)
+        .ok_or(AppError::Forbidden("Token is not valid for this resource"))?;
+
+    let recipients: Vec<RecipientResponse> = event
+        .recipients_of(participant.id)
+        .into_iter()
+        .map(|recipient| RecipientResponse {
+            participant: ParticipantResponse::from(recipient),
+            wishlist: &recipient.wishlist,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(AssignmentResponse {
+        event_id: event.id,
+        status: event.status,
+        budget: event.budget.as_ref(),
+        participant: ParticipantResponse::from(participant),
+        recipient: recipients.first().cloned(),
+        recipients,
+    }))
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+// Anything above this is almost certainly a typo.
+const MAX_AMOUNT_CENTS: i64 = 100_000_000;
+
+/// Non-negative amount in hundredths of the currency unit. Serialized as a
+/// decimal string like "25.00" so no precision is lost on the way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Money(i64);
+
+impl FromStr for Money {
+    type Err = &'static str;
+
+    /// Accepts "25", "25.5", "25.50" and "25,50".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const INVALID: &str = "Invalid amount, please use a number like 25 or 19.99";
+
+        let s = s.trim().replace(',', ".");
+        let (whole, fraction) = s.split_once('.').unwrap_or((&s, ""));
+        if whole.is_empty()
+            || fraction.len() > 2
+            || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+        {
+            return Err(INVALID);
+        }
+
+        // Only digits are left, so this can only fail on overflow.
+        let whole: i64 = whole.parse().map_err(|_| "Amount is too large")?;
+        let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| INVALID)?;
+        let cents = whole
+            .checked_mul(100)
+            .and_then(|c| c.checked_add(fraction))
+            .filter(|&c| c <= MAX_AMOUNT_CENTS)
+            .ok_or("Amount is too large")?;
+        Ok(Money(cents))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Spending limit agreed on for an event. Any of the amounts may be left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Budget {
+    #[serde(default)]
+    pub amount: Option<Money>,
+    #[serde(default)]
+    pub min: Option<Money>,
+    #[serde(default)]
+    pub max: Option<Money>,
+    /// ISO 4217 code, e.g. "EUR".
+    pub currency: String,
+}
+
+// Empty input means "not set".
+fn parse_optional_money(input: &str) -> Result<Option<Money>, &'static str> {
+    if input.trim().is_empty() {
+        return Ok(None);
+    }
+    input.parse().map(Some)
+}
+
+impl Budget {
+    /// Builds a budget from form fields. Returns `None` if no amount is given.
+    pub fn from_inputs(
+        amount: &str,
+        min: &str,
+        max: &str,
+        currency: &str,
+    ) -> Result<Option<Budget>, &'static str> {
+        let budget = Budget {
+            amount: parse_optional_money(amount)?,
+            min: parse_optional_money(min)?,
+            max: parse_optional_money(max)?,
+            currency: currency.trim().to_uppercase(),
+        };
+        if budget.amount.is_none() && budget.min.is_none() && budget.max.is_none() {
+            return Ok(None);
+        }
+        budget.validate()?;
+        Ok(Some(budget))
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.currency.len() != 3 || !self.currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err("Currency must be a three-letter code like EUR");
+        }
+        if self.amount.is_none() && self.min.is_none() && self.max.is_none() {
+            return Err("A budget needs at least one amount");
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err("The minimum budget is above the maximum");
+            }
+        }
+        if let Some(amount) = self.amount {
+            if self.min.is_some_and(|min| amount < min) || self.max.is_some_and(|max| amount > max) {
+                return Err("The budget amount must lie between minimum and maximum");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(s: &str) -> Money {
+        s.parse().unwrap()
+    }
+
+    fn budget(amount: &str, min: &str, max: &str) -> Result<Option<Budget>, &'static str> {
+        Budget::from_inputs(amount, min, max, "eur")
+    }
+
+    #[test]
+    fn parses_amounts() {
+        assert_eq!(money("25"), Money(2500));
+        assert_eq!(money("25,50"), Money(2550));
+        assert_eq!(money("25.5"), Money(2550));
+        assert_eq!(money(" 0.07 "), Money(7));
+        assert_eq!(money("25."), Money(2500));
+        assert_eq!(money("1000000"), Money(MAX_AMOUNT_CENTS));
+        assert_eq!(money("25.5").to_string(), "25.50");
+    }
+
+    #[test]
+    fn rejects_invalid_amounts() {
+        for input in ["", "abc", "25€", "1e3", ".5", "25.505", "-5", "-0.01", "2.5.0", "1 000"] {
+            assert!(input.parse::<Money>().is_err(), "{:?} was accepted", input);
+        }
+        assert_eq!("1000000.01".parse::<Money>(), Err("Amount is too large"));
+        assert_eq!("99999999999999999999".parse::<Money>(), Err("Amount is too large"));
+    }
+
+    #[test]
+    fn builds_budgets_from_inputs() {
+        assert_eq!(budget("", " ", "").unwrap(), None);
+        let range = budget("20", "15", "30").unwrap().unwrap();
+        assert_eq!(range.amount, Some(money("20")));
+        assert_eq!(range.currency, "EUR");
+        assert!(budget("", "", "30").unwrap().is_some());
+    }
+
+    #[test]
+    fn rejects_inconsistent_budgets() {
+        assert_eq!(budget("", "30", "15"), Err("The minimum budget is above the maximum"));
+        assert!(budget("10", "15", "30").is_err());
+        assert!(budget("40", "15", "30").is_err());
+        assert!(budget("20", "", "").unwrap().is_some());
+        assert!(Budget::from_inputs("20", "", "", "EURO").is_err());
+
+        let empty = Budget {
+            amount: None,
+            min: None,
+            max: None,
+            currency: "EUR".to_string(),
+        };
+        assert!(empty.validate().is_err());
+    }
+}
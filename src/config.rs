@@ -0,0 +1,198 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const DEFAULT_MAX_PARTICIPANTS: usize = 500;
+
+#[derive(Debug, Clone)]
+pub enum StorageConfig {
+    Memory,
+    Sqlite { path: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Environment {
+    Development,
+    Production,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionKeyConfig {
+    pub key: Option<String>,
+    pub key_file: Option<PathBuf>,
+    pub previous_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: SmtpTls,
+}
+
+#[derive(Debug, Clone)]
+pub struct MailConfig {
+    /// Mail is disabled when no SMTP host is configured.
+    pub smtp: Option<SmtpConfig>,
+    pub from: String,
+}
+
+/// Limits against abuse of the public endpoints. Zero disables a limit.
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    /// Requests per minute and client IP to creating, joining and searching.
+    pub requests_per_minute: u32,
+    /// Header a reverse proxy puts the client IP in. When unset, the address
+    /// of the connecting peer is used.
+    pub client_ip_header: Option<String>,
+    pub events_per_ip_per_day: u32,
+    pub participants_per_event: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub environment: Environment,
+    pub bind_address: String,
+    /// Externally reachable base URL, put in front of the links in mails and
+    /// QR codes.
+    pub public_url: String,
+    pub storage: StorageConfig,
+    pub session: SessionKeyConfig,
+    pub mail: MailConfig,
+    pub limits: LimitsConfig,
+}
+
+fn parse_var<T: FromStr>(name: &str, default: T) -> Result<T, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid {} '{}'", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        let environment = match env::var("APP_ENV")
+            .unwrap_or_else(|_| "development".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "development" | "dev" => Environment::Development,
+            "production" | "prod" => Environment::Production,
+            other => {
+                return Err(format!(
+                    "Unknown APP_ENV '{}', expected 'development' or 'production'",
+                    other
+                ))
+            }
+        };
+
+        let bind_address =
+            env::var("BIND_ADDRESS").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+
+        let public_url = env::var("PUBLIC_URL")
+            .unwrap_or_else(|_| format!("http://{}", bind_address))
+            .trim_end_matches('/')
+            .to_string();
+
+        let storage = match env::var("STORAGE_BACKEND")
+            .unwrap_or_else(|_| "sqlite".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "memory" => StorageConfig::Memory,
+            "sqlite" => StorageConfig::Sqlite {
+                path: env::var("DATABASE_PATH")
+                    .unwrap_or_else(|_| "wichtel.db".to_string())
+                    .into(),
+            },
+            other => {
+                return Err(format!(
+                    "Unknown STORAGE_BACKEND '{}', expected 'memory' or 'sqlite'",
+                    other
+                ))
+            }
+        };
+
+        let session = SessionKeyConfig {
+            key: env::var("SESSION_KEY").ok().filter(|k| !k.trim().is_empty()),
+            key_file: env::var("SESSION_KEY_FILE").ok().map(PathBuf::from),
+            previous_keys: env::var("SESSION_PREVIOUS_KEYS")
+                .unwrap_or_default()
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|k| !k.is_empty())
+                .map(str::to_string)
+                .collect(),
+        };
+
+        let smtp = match env::var("SMTP_HOST").ok().filter(|h| !h.trim().is_empty()) {
+            Some(host) => Some(SmtpConfig {
+                host,
+                port: match env::var("SMTP_PORT") {
+                    Ok(port) => Some(
+                        port.parse()
+                            .map_err(|_| format!("Invalid SMTP_PORT '{}'", port))?,
+                    ),
+                    Err(_) => None,
+                },
+                username: env::var("SMTP_USERNAME").ok(),
+                password: env::var("SMTP_PASSWORD").ok(),
+                tls: match env::var("SMTP_TLS")
+                    .unwrap_or_else(|_| "starttls".to_string())
+                    .to_lowercase()
+                    .as_str()
+                {
+                    "none" => SmtpTls::None,
+                    "starttls" => SmtpTls::StartTls,
+                    "tls" => SmtpTls::Tls,
+                    other => {
+                        return Err(format!(
+                            "Unknown SMTP_TLS '{}', expected 'none', 'starttls' or 'tls'",
+                            other
+                        ))
+                    }
+                },
+            }),
+            None => None,
+        };
+
+        let mail = MailConfig {
+            smtp,
+            from: env::var("MAIL_FROM")
+                .unwrap_or_else(|_| "Wichtel Loser <wichtel@localhost>".to_string()),
+        };
+
+        let limits = LimitsConfig {
+            requests_per_minute: parse_var("RATE_LIMIT_PER_MINUTE", 30)?,
+            client_ip_header: env::var("TRUSTED_PROXY_HEADER")
+                .ok()
+                .filter(|h| !h.trim().is_empty()),
+            events_per_ip_per_day: parse_var("MAX_EVENTS_PER_IP", 20)?,
+            participants_per_event: parse_var(
+                "MAX_PARTICIPANTS_PER_EVENT",
+                DEFAULT_MAX_PARTICIPANTS,
+            )?,
+        };
+
+        Ok(Self {
+            environment,
+            bind_address,
+            public_url,
+            storage,
+            session,
+            mail,
+            limits,
+        })
+    }
+}
@@ -0,0 +1,162 @@
+use actix_session::{Session, SessionExt};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{mime, web, Error};
+use rand::RngCore;
+
+use crate::error::AppError;
+
+const SESSION_KEY: &str = "csrf_token";
+const FORM_FIELD: &str = "csrf_token";
+const HEADER: &str = "X-CSRF-Token";
+
+/// The token forms of this session have to send back, created on first use.
+pub fn session_token(session: &Session) -> String {
+    if let Ok(Some(token)) = session.get::<String>(SESSION_KEY) {
+        return token;
+    }
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let _ = session.insert(SESSION_KEY, &token);
+    token
+}
+
+/// Compares without stopping at the first difference, so response times
+/// don't give away how much of a guess was right.
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn is_form(req: &ServiceRequest) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<mime::Mime>().ok())
+        .is_some_and(|m| m.essence_str() == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str())
+}
+
+/// Rejects state-changing requests whose CSRF token, sent as form field or
+/// header, does not match the session's. The JSON API authenticates with
+/// bearer tokens instead of cookies and is left alone.
+pub async fn verify(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if safe || req.path().starts_with("/api/") {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let expected = req.get_session().get::<String>(SESSION_KEY).ok().flatten();
+    let mut given = req
+        .headers()
+        .get(HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+    if given.is_none() && is_form(&req) {
+        let body = req.extract::<web::Bytes>().await?;
+        given = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(key, _)| key == FORM_FIELD)
+            .map(|(_, value)| value);
+        req.set_payload(Payload::from(body));
+    }
+
+    match (expected, given) {
+        (Some(expected), Some(given)) if tokens_match(&expected, &given) => {
+            next.call(req).await.map(ServiceResponse::map_into_left_body)
+        }
+        _ => Ok(req
+            .error_response(AppError::Forbidden(
+                "This form has expired. Please go back, reload the page and try again.",
+            ))
+            .map_into_right_body()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_session::storage::CookieSessionStore;
+    use actix_session::SessionMiddleware;
+    use actix_web::cookie::{Cookie, Key};
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    #[test]
+    fn tokens_match_only_when_equal() {
+        assert!(tokens_match("abc123", "abc123"));
+        assert!(!tokens_match("abc123", "abc124"));
+        assert!(!tokens_match("abc123", "abc12"));
+        assert!(!tokens_match("abc123", ""));
+    }
+
+    #[actix_web::test]
+    async fn posts_need_the_session_token() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(verify))
+                .wrap(SessionMiddleware::new(CookieSessionStore::default(), Key::generate()))
+                .route(
+                    "/token",
+                    web::get().to(|session: Session| async move { session_token(&session) }),
+                )
+                // Echoes the body to show the middleware hands it on intact.
+                .route("/submit", web::post().to(|body: String| async move { body })),
+        )
+        .await;
+
+        let resp = call_service(&app, TestRequest::get().uri("/token").to_request()).await;
+        let cookie: Cookie<'static> = resp.response().cookies().next().unwrap().into_owned();
+        let token = String::from_utf8(read_body(resp).await.to_vec()).unwrap();
+
+        let post = |body: String| {
+            TestRequest::post()
+                .uri("/submit")
+                .cookie(cookie.clone())
+                .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+                .set_payload(body)
+        };
+
+        let body = format!("name=Anna&csrf_token={}", token);
+        let resp = call_service(&app, post(body.clone()).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(read_body(resp).await, body.as_bytes());
+
+        let resp = call_service(
+            &app,
+            post(String::new()).insert_header((HEADER, token.as_str())).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let wrong = format!("name=Anna&csrf_token={}", "0".repeat(token.len()));
+        for body in ["name=Anna".to_string(), wrong] {
+            let resp = call_service(&app, post(body).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+
+        // A token is only good with the session it belongs to.
+        let resp = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/submit")
+                .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+                .set_payload(format!("csrf_token={}", token))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
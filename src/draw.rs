@@ -0,0 +1,544 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use uuid::Uuid;
+
+// Exclusions reject some random permutations. This many tries keeps the
+// result uniform in all but very tight setups, which fall back to search.
+const MAX_SAMPLE_ATTEMPTS: usize = 1000;
+// Upper bound on extension steps when searching for a single gift circle.
+const MAX_CYCLE_SEARCH_STEPS: usize = 200_000;
+// How often a multi-gift draw starts over when a later round gets stuck.
+const MAX_ROUND_RESTARTS: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrawMode {
+    /// Any assignment without self-draws; may form several separate circles.
+    #[default]
+    AnyDerangement,
+    /// Everyone forms one big gift circle.
+    SingleCycle,
+}
+
+/// How strictly pairs from earlier years are avoided.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryConstraint {
+    /// Avoided as far as possible; the oldest years are given up first if
+    /// no draw avoids all of them.
+    #[default]
+    Soft,
+    /// Never repeated; the draw fails instead.
+    Hard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DrawError {
+    NotEnoughParticipants,
+    /// Everyone in `givers` may only draw someone from `receivers`, and there
+    /// are fewer receivers than givers.
+    Infeasible {
+        givers: Vec<Uuid>,
+        receivers: Vec<Uuid>,
+    },
+    /// A valid draw exists, but no single circle through everyone was found.
+    NoSingleCycle,
+}
+
+// Bipartite graph of who may draw whom, indexed by position in `ids`.
+struct Graph<'a> {
+    ids: &'a [Uuid],
+    allowed: Vec<Vec<usize>>,
+    matrix: Vec<bool>,
+}
+
+impl<'a> Graph<'a> {
+    fn new(ids: &'a [Uuid], is_forbidden: &impl Fn(Uuid, Uuid) -> bool) -> Self {
+        let n = ids.len();
+        let mut matrix = vec![false; n * n];
+        let allowed = (0..n)
+            .map(|giver| {
+                (0..n)
+                    .filter(|&r| r != giver && !is_forbidden(ids[giver], ids[r]))
+                    .inspect(|&r| matrix[giver * n + r] = true)
+                    .collect()
+            })
+            .collect();
+        Self {
+            ids,
+            allowed,
+            matrix,
+        }
+    }
+
+    fn is_allowed(&self, giver: usize, receiver: usize) -> bool {
+        self.matrix[giver * self.ids.len() + receiver]
+    }
+
+    fn accepts(&self, receiver_of: &[usize]) -> bool {
+        receiver_of
+            .iter()
+            .enumerate()
+            .all(|(giver, &receiver)| self.is_allowed(giver, receiver))
+    }
+
+    // Kuhn's augmenting path algorithm. Returns `receiver_of[giver]` for a
+    // perfect matching, or the givers blocking one per Hall's theorem.
+    fn perfect_matching<R: Rng>(&self, rng: &mut R) -> Result<Vec<usize>, DrawError> {
+        let n = self.ids.len();
+        let mut adjacency = self.allowed.clone();
+        for list in adjacency.iter_mut() {
+            list.shuffle(rng);
+        }
+
+        let mut giver_of: Vec<Option<usize>> = vec![None; n];
+        let mut order: Vec<usize> = (0..n).collect();
+        order.shuffle(rng);
+
+        for &giver in &order {
+            let mut visited = vec![false; n];
+            if !augment(giver, &adjacency, &mut giver_of, &mut visited) {
+                return Err(self.hall_violation(giver, &giver_of));
+            }
+        }
+
+        let mut receiver_of = vec![0; n];
+        for (receiver, giver) in giver_of.iter().enumerate() {
+            if let Some(giver) = giver {
+                receiver_of[*giver] = receiver;
+            }
+        }
+        Ok(receiver_of)
+    }
+
+    // Collects every giver reachable from `unmatched` along alternating paths.
+    // Those givers can together only reach the matched receivers found on the
+    // way, which are one fewer than them.
+    fn hall_violation(&self, unmatched: usize, giver_of: &[Option<usize>]) -> DrawError {
+        let n = self.ids.len();
+        let mut seen_givers = vec![false; n];
+        let mut seen_receivers = vec![false; n];
+        let mut queue = VecDeque::from([unmatched]);
+        seen_givers[unmatched] = true;
+
+        while let Some(giver) = queue.pop_front() {
+            for &receiver in &self.allowed[giver] {
+                if seen_receivers[receiver] {
+                    continue;
+                }
+                seen_receivers[receiver] = true;
+                if let Some(next) = giver_of[receiver] {
+                    if !seen_givers[next] {
+                        seen_givers[next] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        let pick = |seen: &[bool]| {
+            (0..n)
+                .filter(|&i| seen[i])
+                .map(|i| self.ids[i])
+                .collect()
+        };
+        DrawError::Infeasible {
+            givers: pick(&seen_givers),
+            receivers: pick(&seen_receivers),
+        }
+    }
+}
+
+fn augment(
+    giver: usize,
+    adjacency: &[Vec<usize>],
+    giver_of: &mut [Option<usize>],
+    visited: &mut [bool],
+) -> bool {
+    for &receiver in &adjacency[giver] {
+        if visited[receiver] {
+            continue;
+        }
+        visited[receiver] = true;
+        let free = match giver_of[receiver] {
+            None => true,
+            Some(other) => augment(other, adjacency, giver_of, visited),
+        };
+        if free {
+            giver_of[receiver] = Some(giver);
+            return true;
+        }
+    }
+    false
+}
+
+/// Uniformly random derangement of `0..n` (`n >= 2`) in linear time.
+///
+/// Uses the recurrence D(m) = (m-1)·D(m-1) + (m-1)·D(m-2): the last element
+/// either forms a 2-cycle with one of the others, or is spliced into the cycle
+/// of one of them in a derangement of the remaining m-1. The choices are made
+/// top-down with exactly those weights and then replayed bottom-up.
+pub fn random_derangement<R: Rng>(n: usize, rng: &mut R) -> Vec<usize> {
+    enum Step {
+        TwoCycle(usize),
+        Splice(usize),
+    }
+
+    // ratio = D(m-1) / D(m-2), tracked as a ratio so large groups don't
+    // overflow. Starts at D(2) / D(1) = 1 / 0.
+    let mut ratio = f64::INFINITY;
+    let mut two_cycle_probability = vec![1.0; n + 1];
+    for (m, p) in two_cycle_probability.iter_mut().enumerate().skip(3) {
+        if m > 3 {
+            ratio = (m - 2) as f64 * (1.0 + 1.0 / ratio);
+        }
+        *p = 1.0 / (1.0 + ratio);
+    }
+
+    let mut steps = Vec::new();
+    let mut m = n;
+    while m > 0 {
+        let j = rng.gen_range(0..m - 1);
+        if rng.gen::<f64>() < two_cycle_probability[m] {
+            steps.push(Step::TwoCycle(j));
+            m -= 2;
+        } else {
+            steps.push(Step::Splice(j));
+            m -= 1;
+        }
+    }
+
+    let mut perm: Vec<usize> = Vec::with_capacity(n);
+    let mut inverse: Vec<usize> = Vec::with_capacity(n);
+    for step in steps.into_iter().rev() {
+        let size = perm.len();
+        match step {
+            Step::Splice(j) => {
+                perm.push(perm[j]);
+                inverse[perm[j]] = size;
+                perm[j] = size;
+                inverse.push(j);
+            }
+            Step::TwoCycle(j) => {
+                perm.extend([size + 1, size]);
+                inverse.extend([size + 1, size]);
+                if j != size {
+                    swap_labels(&mut perm, &mut inverse, j, size);
+                }
+            }
+        }
+    }
+    perm
+}
+
+// Conjugates the permutation with the transposition (x y).
+fn swap_labels(perm: &mut [usize], inverse: &mut [usize], x: usize, y: usize) {
+    perm.swap(x, y);
+    inverse[perm[x]] = x;
+    inverse[perm[y]] = y;
+    let (ix, iy) = (inverse[x], inverse[y]);
+    perm[ix] = y;
+    perm[iy] = x;
+    inverse[x] = iy;
+    inverse[y] = ix;
+}
+
+/// Uniformly random permutation of `0..n` consisting of a single cycle
+/// (Sattolo's algorithm).
+pub fn random_single_cycle<R: Rng>(n: usize, rng: &mut R) -> Vec<usize> {
+    let mut perm: Vec<usize> = (0..n).collect();
+    for i in (1..n).rev() {
+        let j = rng.gen_range(0..i);
+        perm.swap(i, j);
+    }
+    perm
+}
+
+// Depth-first search for a Hamiltonian cycle through the allowed pairs,
+// giving up after `MAX_CYCLE_SEARCH_STEPS` extensions.
+fn search_single_cycle<R: Rng>(graph: &Graph, rng: &mut R) -> Option<Vec<usize>> {
+    let n = graph.ids.len();
+    let mut adjacency = graph.allowed.clone();
+    for list in adjacency.iter_mut() {
+        list.shuffle(rng);
+    }
+
+    let start = rng.gen_range(0..n);
+    let mut on_path = vec![false; n];
+    let mut path = vec![start];
+    // Next candidate index to try for each position on the path
+    let mut cursor = vec![0];
+    on_path[start] = true;
+
+    let mut steps = 0;
+    while let Some(&current) = path.last() {
+        if path.len() == n && graph.is_allowed(current, start) {
+            let mut receiver_of = vec![0; n];
+            for (i, &giver) in path.iter().enumerate() {
+                receiver_of[giver] = path[(i + 1) % n];
+            }
+            return Some(receiver_of);
+        }
+
+        let position = cursor.len() - 1;
+        let next = adjacency[current][cursor[position]..]
+            .iter()
+            .position(|&r| !on_path[r]);
+        match next {
+            Some(offset) if path.len() < n => {
+                steps += 1;
+                if steps > MAX_CYCLE_SEARCH_STEPS {
+                    return None;
+                }
+                let receiver = adjacency[current][cursor[position] + offset];
+                cursor[position] += offset + 1;
+                on_path[receiver] = true;
+                path.push(receiver);
+                cursor.push(0);
+            }
+            _ => {
+                on_path[current] = false;
+                path.pop();
+                cursor.pop();
+            }
+        }
+    }
+    None
+}
+
+/// Draws a receiver for every participant so that nobody draws themselves and
+/// no pair for which `is_forbidden(giver, receiver)` holds is used.
+///
+/// Without exclusions the result is uniformly distributed over all valid
+/// draws of the given mode. Exclusions are handled by rejection sampling,
+/// which stays uniform, with a bounded search as fallback when they are too
+/// tight for sampling to succeed.
+pub fn generate_assignments<R: Rng>(
+    participant_ids: &[Uuid],
+    mode: DrawMode,
+    is_forbidden: impl Fn(Uuid, Uuid) -> bool,
+    rng: &mut R,
+) -> Result<Vec<(Uuid, Uuid)>, DrawError> {
+    let n = participant_ids.len();
+    if n < 2 {
+        return Err(DrawError::NotEnoughParticipants);
+    }
+
+    let graph = Graph::new(participant_ids, &is_forbidden);
+    // Fails early with an explanation if no valid draw exists at all
+    let matching = graph.perfect_matching(rng)?;
+
+    let sample = |rng: &mut R| match mode {
+        DrawMode::AnyDerangement => random_derangement(n, rng),
+        DrawMode::SingleCycle => random_single_cycle(n, rng),
+    };
+    let sampled = (0..MAX_SAMPLE_ATTEMPTS)
+        .map(|_| sample(rng))
+        .find(|receiver_of| graph.accepts(receiver_of));
+
+    let receiver_of = match (sampled, mode) {
+        (Some(receiver_of), _) => receiver_of,
+        (None, DrawMode::AnyDerangement) => matching,
+        (None, DrawMode::SingleCycle) => {
+            search_single_cycle(&graph, rng).ok_or(DrawError::NoSingleCycle)?
+        }
+    };
+
+    Ok(participant_ids
+        .iter()
+        .zip(receiver_of)
+        .map(|(&giver, receiver)| (giver, participant_ids[receiver]))
+        .collect())
+}
+
+/// Draws `gifts` distinct receivers for every participant by running
+/// [`generate_assignments`] once per gift, each round avoiding the pairs of
+/// the rounds before. Pairs are returned round by round.
+///
+/// Without exclusions every round succeeds as long as there are more
+/// participants than gifts: the pairs still allowed form a regular bipartite
+/// graph, which always has a perfect matching. Exclusions can make a greedy
+/// round fail, in which case the whole draw is started over a few times.
+pub fn generate_multi_assignments<R: Rng>(
+    participant_ids: &[Uuid],
+    mode: DrawMode,
+    gifts: usize,
+    is_forbidden: impl Fn(Uuid, Uuid) -> bool,
+    rng: &mut R,
+) -> Result<Vec<(Uuid, Uuid)>, DrawError> {
+    if participant_ids.len() <= gifts {
+        return Err(DrawError::NotEnoughParticipants);
+    }
+
+    let mut last_error = DrawError::NotEnoughParticipants;
+    for _ in 0..MAX_ROUND_RESTARTS {
+        let mut used = HashSet::new();
+        let mut pairs = Vec::with_capacity(participant_ids.len() * gifts);
+        for round in 0..gifts {
+            let drawn = generate_assignments(
+                participant_ids,
+                mode,
+                |giver, receiver| is_forbidden(giver, receiver) || used.contains(&(giver, receiver)),
+                rng,
+            );
+            match drawn {
+                Ok(drawn) => {
+                    used.extend(drawn.iter().copied());
+                    pairs.extend(drawn);
+                }
+                // Nothing earlier rounds did can help the first one
+                Err(e) if round == 0 => return Err(e),
+                Err(e) => {
+                    last_error = e;
+                    break;
+                }
+            }
+        }
+        if pairs.len() == participant_ids.len() * gifts {
+            return Ok(pairs);
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+
+    fn ids(n: usize) -> Vec<Uuid> {
+        (1..=n as u128).map(Uuid::from_u128).collect()
+    }
+
+    fn is_permutation(perm: &[usize]) -> bool {
+        let mut seen = vec![false; perm.len()];
+        perm.iter().all(|&i| i < perm.len() && !std::mem::replace(&mut seen[i], true))
+    }
+
+    fn cycle_length(receiver_of: &HashMap<Uuid, Uuid>, start: Uuid) -> usize {
+        let mut length = 1;
+        let mut current = receiver_of[&start];
+        while current != start {
+            current = receiver_of[&current];
+            length += 1;
+        }
+        length
+    }
+
+    #[test]
+    fn derangements_have_no_fixed_points() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for n in 2..40 {
+            for _ in 0..50 {
+                let perm = random_derangement(n, &mut rng);
+                assert!(is_permutation(&perm));
+                assert!(perm.iter().enumerate().all(|(i, &j)| i != j));
+            }
+        }
+    }
+
+    #[test]
+    fn derangements_are_uniform() {
+        // There are 9 derangements of 4 elements.
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut counts: HashMap<Vec<usize>, usize> = HashMap::new();
+        for _ in 0..9000 {
+            *counts.entry(random_derangement(4, &mut rng)).or_default() += 1;
+        }
+        assert_eq!(counts.len(), 9);
+        assert!(counts.values().all(|&c| (800..1200).contains(&c)), "{:?}", counts);
+    }
+
+    #[test]
+    fn sattolo_gives_a_single_cycle() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for n in 2..30 {
+            let perm = random_single_cycle(n, &mut rng);
+            assert!(is_permutation(&perm));
+            let mut length = 1;
+            let mut current = perm[0];
+            while current != 0 {
+                current = perm[current];
+                length += 1;
+            }
+            assert_eq!(length, n);
+        }
+    }
+
+    #[test]
+    fn exclusions_are_respected() {
+        let people = ids(8);
+        // Tight enough that sampling mostly fails and the fallbacks run.
+        let forbidden = |giver: Uuid, receiver: Uuid| {
+            let (g, r) = (giver.as_u128(), receiver.as_u128());
+            r != g % 8 + 1 && r != (g + 1) % 8 + 1
+        };
+        let mut rng = StdRng::seed_from_u64(4);
+        for mode in [DrawMode::AnyDerangement, DrawMode::SingleCycle] {
+            for _ in 0..20 {
+                let pairs = generate_assignments(&people, mode, forbidden, &mut rng).unwrap();
+                assert_eq!(pairs.len(), people.len());
+                assert!(pairs.iter().all(|&(g, r)| g != r && !forbidden(g, r)));
+                let receiver_of: HashMap<Uuid, Uuid> = pairs.into_iter().collect();
+                let mut receivers: Vec<Uuid> = receiver_of.values().copied().collect();
+                receivers.sort();
+                assert_eq!(receivers, people);
+                if mode == DrawMode::SingleCycle {
+                    assert_eq!(cycle_length(&receiver_of, people[0]), people.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn infeasible_exclusions_name_the_blocking_group() {
+        let people = ids(4);
+        // Nobody may draw the first person.
+        let blocked = people[0];
+        let mut rng = StdRng::seed_from_u64(5);
+        for mode in [DrawMode::AnyDerangement, DrawMode::SingleCycle] {
+            let result = generate_assignments(&people, mode, |_, r| r == blocked, &mut rng);
+            match result {
+                Err(DrawError::Infeasible { givers, receivers }) => {
+                    assert_eq!(givers, people);
+                    assert_eq!(receivers, people[1..]);
+                }
+                other => panic!("expected a Hall violation, got {:?}", other),
+            }
+        }
+        assert_eq!(
+            generate_assignments(&people[..1], DrawMode::AnyDerangement, |_, _| false, &mut rng),
+            Err(DrawError::NotEnoughParticipants)
+        );
+    }
+
+    #[test]
+    fn multi_gift_rounds_never_repeat_a_pair() {
+        let people = ids(7);
+        let (a, b) = (people[0], people[1]);
+        let forbidden = |g: Uuid, r: Uuid| (g, r) == (a, b) || (g, r) == (b, a);
+        let mut rng = StdRng::seed_from_u64(6);
+        for mode in [DrawMode::AnyDerangement, DrawMode::SingleCycle] {
+            for gifts in 1..=3 {
+                let pairs =
+                    generate_multi_assignments(&people, mode, gifts, forbidden, &mut rng).unwrap();
+                assert_eq!(pairs.len(), people.len() * gifts);
+                let distinct: HashSet<(Uuid, Uuid)> = pairs.iter().copied().collect();
+                assert_eq!(distinct.len(), pairs.len());
+                assert!(pairs.iter().all(|&(g, r)| g != r && !forbidden(g, r)));
+                for person in &people {
+                    assert_eq!(pairs.iter().filter(|(g, _)| g == person).count(), gifts);
+                    assert_eq!(pairs.iter().filter(|(_, r)| r == person).count(), gifts);
+                }
+            }
+        }
+        assert_eq!(
+            generate_multi_assignments(&people[..3], DrawMode::AnyDerangement, 3, |_, _| false, &mut rng),
+            Err(DrawError::NotEnoughParticipants)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, Accept, Header};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{mime, web, Error, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use tera::Context;
+
+use crate::i18n::{self, Templates};
+
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Unauthorized,
+    Forbidden(&'static str),
+    NotFound(&'static str),
+    Conflict(String),
+    TooManyRequests {
+        message: &'static str,
+        retry_after: Duration,
+    },
+    Internal,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: &'a str,
+}
+
+impl AppError {
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Unauthorized => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::TooManyRequests { .. } => "too_many_requests",
+            AppError::Internal => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::BadRequest(msg) | AppError::Conflict(msg) => write!(f, "{}", msg),
+            AppError::Unauthorized => write!(f, "Missing or malformed bearer token"),
+            AppError::Forbidden(msg) | AppError::NotFound(msg) => write!(f, "{}", msg),
+            AppError::TooManyRequests { message, .. } => write!(f, "{}", message),
+            AppError::Internal => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // JSON by default; `negotiate` swaps in the error page for browsers.
+    fn error_response(&self) -> HttpResponse {
+        let message = self.to_string();
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            AppError::Unauthorized => {
+                response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            AppError::TooManyRequests { retry_after, .. } => {
+                response.insert_header((header::RETRY_AFTER, retry_after.as_secs().max(1)));
+            }
+            _ => {}
+        }
+        response.json(serde_json::json!({
+            "error": ErrorBody {
+                code: self.code(),
+                message: &message,
+            }
+        }))
+    }
+}
+
+// Whichever of JSON and HTML the client ranks higher. Clients that accept
+// anything get JSON from the API and HTML everywhere else.
+fn prefers_json(req: &HttpRequest) -> bool {
+    let ranked = Accept::parse(req).map(|a| a.ranked()).unwrap_or_default();
+    for accepted in ranked {
+        match (accepted.type_(), accepted.subtype()) {
+            (mime::APPLICATION, mime::JSON) => return true,
+            (mime::TEXT, mime::HTML) => return false,
+            _ => {}
+        }
+    }
+    req.path().starts_with("/api/")
+}
+
+/// Renders `AppError`s as the error page, with their status code, unless the
+/// client prefers JSON.
+pub async fn negotiate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let res = next.call(req).await?;
+
+    let Some(error) = res.response().error().and_then(|e| e.as_error::<AppError>()) else {
+        return Ok(res.map_into_left_body());
+    };
+    if prefers_json(res.request()) {
+        return Ok(res.map_into_left_body());
+    }
+    let Some(templates) = res.request().app_data::<web::Data<Templates>>() else {
+        return Ok(res.map_into_left_body());
+    };
+
+    let locale = i18n::request_locale(res.request());
+    let mut context = Context::new();
+    context.insert("error", &error.to_string());
+    context.insert("lang", locale.code());
+    let mut page = match templates.get(locale).render("error.html", &context) {
+        Ok(body) => HttpResponse::build(error.status_code())
+            .content_type("text/html; charset=utf-8")
+            .body(body),
+        Err(e) => {
+            eprintln!("Template error: {}", e);
+            return Ok(res.map_into_left_body());
+        }
+    };
+    for name in [header::WWW_AUTHENTICATE, header::RETRY_AFTER] {
+        if let Some(value) = res.headers().get(&name) {
+            page.headers_mut().insert(name, value.clone());
+        }
+    }
+    Ok(res.into_response(page).map_into_right_body())
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Local, Utc};
+use csv::Writer;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::models::{EventStatus, WichtelEvent};
+
+// Tells exports apart from any other JSON someone might upload.
+const FORMAT: &str = "wichtel_loser-event";
+// Bump when an event can no longer be read by older versions.
+const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Export<'a> {
+    format: &'static str,
+    version: u32,
+    exported_at: DateTime<Utc>,
+    event: &'a WichtelEvent,
+}
+
+#[derive(Deserialize)]
+struct StoredExport {
+    format: String,
+    version: u32,
+    event: WichtelEvent,
+}
+
+/// Everything there is about the event, including the draw, PINs and
+/// personal links, in the form `from_json` reads back.
+///
+/// The seed of a pending commitment is left out: with it, the organizer could
+/// compute the draw in advance and withdraw until they like the outcome. The
+/// seed of a completed draw stays in, participants' proofs are made from it.
+pub fn to_json(event: &WichtelEvent) -> Result<String, AppError> {
+    let mut event = event.clone();
+    if let Some(pending) = &mut event.draw_commitment {
+        pending.seed.clear();
+    }
+    let export = Export {
+        format: FORMAT,
+        version: VERSION,
+        exported_at: Utc::now(),
+        event: &event,
+    };
+    serde_json::to_string_pretty(&export).map_err(|e| {
+        log::error!("Failed to export event {}: {}", event.id, e);
+        AppError::Internal
+    })
+}
+
+pub fn from_json(input: &str) -> Result<WichtelEvent, AppError> {
+    let export: StoredExport = serde_json::from_str(input.trim())
+        .map_err(|e| AppError::BadRequest(format!("This is not an event export: {}", e)))?;
+    if export.format != FORMAT {
+        return Err(AppError::BadRequest("This is not an event export".to_string()));
+    }
+    if export.version > VERSION {
+        return Err(AppError::BadRequest(
+            "This export was made by a newer version and cannot be read here".to_string(),
+        ));
+    }
+    export
+        .event
+        .check_consistency()
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    Ok(export.event)
+}
+
+/// One row per participant with the time they joined, and with
+/// `with_assignments` whom they drew.
+pub fn participants_csv(event: &WichtelEvent, with_assignments: bool) -> Result<Vec<u8>, AppError> {
+    if with_assignments && event.status != EventStatus::Closed {
+        return Err(AppError::Conflict("Names have not been drawn yet".to_string()));
+    }
+    let mut participants: Vec<_> = event.participants.values().collect();
+    participants.sort_by_key(|p| p.joined_at);
+
+    let failed = |e: &dyn std::fmt::Display| {
+        log::error!("Failed to export participants of event {}: {}", event.id, e);
+        AppError::Internal
+    };
+    let mut writer = Writer::from_writer(Vec::new());
+    let mut header = vec!["name", "email", "joined_at"];
+    if with_assignments {
+        header.push("draws");
+    }
+    writer.write_record(&header).map_err(|e| failed(&e))?;
+    for participant in participants {
+        let mut record = vec![
+            participant.name.clone(),
+            participant.email.clone().unwrap_or_default(),
+            participant
+                .joined_at
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+        ];
+        if with_assignments {
+            let recipients: Vec<&str> = event
+                .recipients_of(participant.id)
+                .into_iter()
+                .map(|r| r.name.as_str())
+                .collect();
+            record.push(recipients.join("; "));
+        }
+        writer.write_record(&record).map_err(|e| failed(&e))?;
+    }
+    writer.into_inner().map_err(|e| failed(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn committed_event() -> WichtelEvent {
+        let mut event = WichtelEvent::new("Office".to_string());
+        for name in ["Anna", "Ben", "Clara", "Dora"] {
+            event.add_participant(name.to_string(), None);
+        }
+        event.publish_commitment().unwrap();
+        event
+    }
+
+    #[test]
+    fn pending_seed_is_not_exported() {
+        let event = committed_event();
+        let seed = event.draw_commitment.as_ref().unwrap().seed.clone();
+
+        let json = to_json(&event).unwrap();
+        assert!(!json.contains(&seed));
+
+        let imported = from_json(&json).unwrap();
+        let pending = imported.draw_commitment.as_ref().unwrap();
+        assert!(pending.seed.is_empty());
+        assert_eq!(pending.commitment, event.draw_commitment.as_ref().unwrap().commitment);
+    }
+
+    #[test]
+    fn redacted_commitment_cannot_be_drawn_from() {
+        let event = committed_event();
+        let mut imported = from_json(&to_json(&event).unwrap()).unwrap();
+
+        assert!(matches!(imported.close_and_assign(), Err(AppError::Conflict(_))));
+        assert_eq!(imported.status, EventStatus::Open);
+        assert!(imported.draw_record.is_none());
+
+        imported.withdraw_commitment().unwrap();
+        imported.close_and_assign().unwrap();
+        assert!(imported.draw_record.as_ref().unwrap().commitment_matches());
+    }
+
+    #[test]
+    fn completed_draw_stays_verifiable() {
+        let mut event = committed_event();
+        event.close_and_assign().unwrap();
+
+        let imported = from_json(&to_json(&event).unwrap()).unwrap();
+        let record = imported.draw_record.as_ref().unwrap();
+        assert!(record.commitment_matches());
+        assert!(record.root_matches());
+    }
+}
@@ -1,27 +1,84 @@
-use actix_session::Session;
-use actix_web::{get, post, web, HttpResponse, Result};
+use actix_session::{Session, SessionExt};
+use actix_web::dev::Payload;
+use actix_web::cookie::{time::Duration as CookieDuration, Cookie, SameSite};
+use actix_web::http::{header, StatusCode, Uri};
+use actix_web::{get, post, web, FromRequest, HttpRequest, HttpResponse, ResponseError, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 use serde::{Deserialize, Serialize};
-use tera::{Context, Tera};
+use std::future::{ready, Ready};
+use tera::Context;
 use uuid::Uuid;
 
-use crate::models::EventStatus;
+use crate::budget::Budget;
+use crate::csrf;
+use crate::draw::{DrawMode, HistoryConstraint};
+use crate::error::AppError;
+use crate::export;
+use crate::i18n::{self, Locale, Templates};
+use crate::import;
+use crate::mailer::{self, Mailer};
+use crate::models::{
+    EventStatus, Exclusion, MessageAuthor, MessageThread, Participant, WichtelEvent, Wishlist,
+    MAX_GIFTS_PER_PERSON,
+};
+use crate::qr::{QrCodes, QrFormat};
+use crate::rate_limit::RateLimiter;
 use crate::state::AppState;
+use crate::verification::PairProof;
 
 #[derive(Debug, Deserialize)]
 pub struct CreateEventForm {
     pub name: String,
+    #[serde(default)]
+    pub draw_mode: DrawMode,
+    #[serde(default)]
+    pub gifts_per_person: Option<usize>,
+    #[serde(default)]
+    pub exchange_date: String,
+    #[serde(default)]
+    pub registration_deadline: String,
+    #[serde(flatten)]
+    pub budget: BudgetForm,
+    #[serde(default)]
+    pub language: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetForm {
+    #[serde(default)]
+    pub budget_amount: String,
+    #[serde(default)]
+    pub budget_min: String,
+    #[serde(default)]
+    pub budget_max: String,
+    #[serde(default)]
+    pub budget_currency: String,
+}
+
+impl BudgetForm {
+    fn parse(&self) -> Result<Option<Budget>, &'static str> {
+        Budget::from_inputs(
+            &self.budget_amount,
+            &self.budget_min,
+            &self.budget_max,
+            &self.budget_currency,
+        )
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct JoinEventForm {
     pub name: String,
+    #[serde(default)]
+    pub email: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ConfirmIdentityForm {
     pub participant_id: String,
+    pub pin: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -31,8 +88,53 @@ pub struct FuzzyMatch {
     pub score: i64,
 }
 
-fn render_template(tera: &Tera, template: &str, context: &Context) -> HttpResponse {
-    match tera.render(template, context) {
+/// Templates together with what every page needs from the request: the
+/// language to show and the CSRF token its forms send back.
+#[derive(Clone)]
+pub struct Pages {
+    templates: web::Data<Templates>,
+    locale: Locale,
+    // Picked with the language switch, which beats the event's language.
+    locale_chosen: bool,
+    csrf_token: String,
+}
+
+impl Pages {
+    /// Shows pages of `event` in its language, unless the visitor picked one.
+    fn for_event(&self, event: &WichtelEvent) -> Pages {
+        let mut pages = self.clone();
+        if let (false, Some(language)) = (self.locale_chosen, event.language) {
+            pages.locale = language;
+        }
+        pages
+    }
+}
+
+impl FromRequest for Pages {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let chosen = i18n::chosen_locale(req);
+        let pages = req
+            .app_data::<web::Data<Templates>>()
+            .cloned()
+            .map(|templates| Pages {
+                templates,
+                locale: chosen.unwrap_or_else(|| i18n::request_locale(req)),
+                locale_chosen: chosen.is_some(),
+                csrf_token: csrf::session_token(&req.get_session()),
+            })
+            .ok_or_else(|| AppError::Internal.into());
+        ready(pages)
+    }
+}
+
+fn render_template(pages: &Pages, template: &str, context: &Context) -> HttpResponse {
+    let mut context = context.clone();
+    context.insert("csrf_token", &pages.csrf_token);
+    context.insert("lang", pages.locale.code());
+    match pages.templates.get(pages.locale).render(template, &context) {
         Ok(body) => HttpResponse::Ok().content_type("text/html; charset=utf-8").body(body),
         Err(e) => {
             eprintln!("Template error: {}", e);
@@ -42,37 +144,153 @@ fn render_template(tera: &Tera, template: &str, context: &Context) -> HttpRespon
 }
 
 #[get("/")]
-pub async fn index(tera: web::Data<Tera>) -> HttpResponse {
+pub async fn index(pages: Pages) -> HttpResponse {
     let context = Context::new();
-    render_template(&tera, "index.html", &context)
+    render_template(&pages, "index.html", &context)
+}
+
+// Whether `path` stays on this site. Browsers read "//host" and "/\host" as
+// links to another host.
+fn is_local_path(path: &str) -> bool {
+    path.starts_with('/') && !path.starts_with("//") && !path.starts_with("/\\")
+}
+
+// The language switch. Remembers the choice in a cookie and goes back to the
+// page it was made on.
+#[get("/lang/{code}")]
+pub async fn choose_language(path: web::Path<String>, req: HttpRequest) -> Result<HttpResponse> {
+    let locale = Locale::from_code(&path).ok_or(AppError::NotFound("Unknown language"))?;
+
+    let back = req
+        .headers()
+        .get(header::REFERER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<Uri>().ok())
+        .and_then(|uri| uri.path_and_query().map(|pq| pq.to_string()))
+        .filter(|path| is_local_path(path))
+        .unwrap_or_else(|| "/".to_string());
+    let cookie = Cookie::build(i18n::COOKIE_NAME, locale.code())
+        .path("/")
+        .max_age(CookieDuration::days(365))
+        .same_site(SameSite::Lax)
+        .finish();
+
+    Ok(HttpResponse::Found()
+        .insert_header(("Location", back))
+        .cookie(cookie)
+        .finish())
+}
+
+// Fallback for every unknown path.
+pub async fn not_found() -> Result<HttpResponse> {
+    Err(AppError::NotFound("Page not found").into())
 }
 
 #[get("/create")]
-pub async fn create_event_page(tera: web::Data<Tera>) -> HttpResponse {
+pub async fn create_event_page(pages: Pages) -> HttpResponse {
     let context = Context::new();
-    render_template(&tera, "create.html", &context)
+    render_template(&pages, "create.html", &context)
 }
 
 #[post("/create")]
 pub async fn create_event(
+    req: HttpRequest,
     form: web::Form<CreateEventForm>,
     state: web::Data<AppState>,
-    tera: web::Data<Tera>,
-) -> HttpResponse {
+    limiter: web::Data<RateLimiter>,
+    pages: Pages,
+) -> Result<HttpResponse> {
     let name = form.name.trim().to_string();
     if name.is_empty() {
         let mut context = Context::new();
         context.insert("error", "Event name cannot be empty");
-        return render_template(&tera, "create.html", &context);
+        return Ok(render_failed(&pages, "create.html", &context, StatusCode::BAD_REQUEST));
     }
 
-    let event = state.create_event(name);
-    
+    let settings = parse_date(&form.exchange_date).and_then(|date| {
+        Ok((
+            date,
+            parse_local_datetime(&form.registration_deadline)?,
+            form.budget.parse()?,
+        ))
+    });
+    let (exchange_date, registration_deadline, budget) = match settings {
+        Ok(d) => d,
+        Err(e) => {
+            let mut context = Context::new();
+            context.insert("error", e);
+            return Ok(render_failed(&pages, "create.html", &context, StatusCode::BAD_REQUEST));
+        }
+    };
+
+    let mut event = WichtelEvent::new(name);
+    let settings = event
+        .set_gifts_per_person(form.gifts_per_person.unwrap_or(1))
+        .and_then(|_| event.set_registration_deadline(registration_deadline));
+    if let Err(e) = settings {
+        let mut context = Context::new();
+        context.insert("error", e);
+        return Ok(render_failed(&pages, "create.html", &context, StatusCode::BAD_REQUEST));
+    }
+    event.draw_mode = form.draw_mode;
+    event.exchange_date = exchange_date;
+    event.budget = budget;
+    event.language = Locale::from_code(&form.language);
+
+    limiter.check_new_event(&req)?;
+    let event = state.create_event(event).ok_or(AppError::Internal)?;
+    let pages = pages.for_event(&event);
+
+    let mut context = Context::new();
+    context.insert("event", &event);
+    context.insert("organizer_url", &format!("/event/{}/manage/{}", event.id, event.organizer_token));
+    context.insert("organizer_token", &event.organizer_token.to_string());
+    context.insert("invite_url", &format!("/join/{}", event.invite_code));
+    Ok(render_template(&pages, "event_created.html", &context))
+}
+
+#[get("/import")]
+pub async fn import_event_page(pages: Pages) -> HttpResponse {
+    let context = Context::new();
+    render_template(&pages, "import_event.html", &context)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportEventForm {
+    pub event: String,
+}
+
+// Recreates an event from the JSON export of another instance.
+#[post("/import")]
+pub async fn import_event(
+    req: HttpRequest,
+    form: web::Form<ImportEventForm>,
+    state: web::Data<AppState>,
+    limiter: web::Data<RateLimiter>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let imported = export::from_json(&form.event).and_then(|event| {
+        limiter.check_new_event(&req)?;
+        state.import_event(event)
+    });
+    let event = match imported {
+        Ok(event) => event,
+        Err(e @ (AppError::BadRequest(_) | AppError::Conflict(_))) => {
+            let mut context = Context::new();
+            context.insert("error", &e.to_string());
+            return Ok(render_failed(&pages, "import_event.html", &context, e.status_code()));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let pages = pages.for_event(&event);
+
     let mut context = Context::new();
     context.insert("event", &event);
+    context.insert("imported", &true);
     context.insert("organizer_url", &format!("/event/{}/manage/{}", event.id, event.organizer_token));
+    context.insert("organizer_token", &event.organizer_token.to_string());
     context.insert("invite_url", &format!("/join/{}", event.invite_code));
-    render_template(&tera, "event_created.html", &context)
+    Ok(render_template(&pages, "event_created.html", &context))
 }
 
 #[get("/join/{invite_code}")]
@@ -80,43 +298,38 @@ pub async fn join_page(
     path: web::Path<String>,
     state: web::Data<AppState>,
     session: Session,
-    tera: web::Data<Tera>,
-) -> HttpResponse {
+    pages: Pages,
+) -> Result<HttpResponse> {
     let invite_code = path.into_inner();
     
-    let event = match state.get_event_by_invite_code(&invite_code) {
-        Some(e) => e,
-        None => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid invite code");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+    let event = state
+        .get_event_by_invite_code(&invite_code)
+        .ok_or(AppError::NotFound("Invalid invite code"))?;
+    let pages = pages.for_event(&event);
 
     // Check if user already has a cookie for this event
-    let session_key = format!("participant_{}", event.id);
-    if let Ok(Some(participant_id)) = session.get::<String>(&session_key) {
-        if let Ok(pid) = Uuid::parse_str(&participant_id) {
-            if event.participants.contains_key(&pid) {
-                // Redirect to view assignment
-                return HttpResponse::Found()
-                    .insert_header(("Location", format!("/event/{}/view", event.id)))
-                    .finish();
-            }
-        }
+    if session_participant(&session, &event).is_some() {
+        // Redirect to view assignment
+        return Ok(HttpResponse::Found()
+            .insert_header(("Location", format!("/event/{}/view", event.id)))
+            .finish());
     }
 
     let mut context = Context::new();
     context.insert("event", &event);
     context.insert("invite_code", &invite_code);
+    insert_budget(&mut context, &event);
+    if let Some(deadline) = event.registration_deadline {
+        context.insert("deadline_display", &format_local(deadline, "%d.%m.%Y %H:%M"));
+    }
     
-    if event.status == EventStatus::Closed {
+    if event.accepts_participants() {
+        context.insert("is_closed", &false);
+        Ok(render_template(&pages, "join.html", &context))
+    } else {
         // Event is closed, show identity selection
         context.insert("is_closed", &true);
-        render_template(&tera, "join.html", &context)
-    } else {
-        context.insert("is_closed", &false);
-        render_template(&tera, "join.html", &context)
+        Ok(render_template(&pages, "join.html", &context))
     }
 }
 
@@ -125,321 +338,1449 @@ pub async fn join_event(
     path: web::Path<String>,
     form: web::Form<JoinEventForm>,
     state: web::Data<AppState>,
+    mailer: web::Data<Mailer>,
     session: Session,
-    tera: web::Data<Tera>,
-) -> HttpResponse {
+    pages: Pages,
+) -> Result<HttpResponse> {
     let invite_code = path.into_inner();
     
-    let event = match state.get_event_by_invite_code(&invite_code) {
-        Some(e) => e,
-        None => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid invite code");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+    let event = state
+        .get_event_by_invite_code(&invite_code)
+        .ok_or(AppError::NotFound("Invalid invite code"))?;
+    let pages = pages.for_event(&event);
 
-    if event.status == EventStatus::Closed {
-        let mut context = Context::new();
-        context.insert("error", "This event is already closed for new participants");
-        return render_template(&tera, "error.html", &context);
+    if !event.accepts_participants() {
+        return Err(AppError::Conflict(
+            "This event is already closed for new participants".to_string(),
+        )
+        .into());
     }
 
     let name = form.name.trim().to_string();
-    if name.is_empty() {
+    let email = mailer::parse_email(&form.email);
+    let error = match (&email, name.is_empty()) {
+        (_, true) => Some("Name cannot be empty"),
+        (Err(e), _) => Some(*e),
+        _ => None,
+    };
+    if let Some(error) = error {
         let mut context = Context::new();
         context.insert("event", &event);
         context.insert("invite_code", &invite_code);
         context.insert("is_closed", &false);
-        context.insert("error", "Name cannot be empty");
-        return render_template(&tera, "join.html", &context);
+        context.insert("error", error);
+        insert_budget(&mut context, &event);
+        return Ok(render_failed(&pages, "join.html", &context, StatusCode::BAD_REQUEST));
     }
 
-    let participant_id = match state.add_participant(&event.id, name.clone()) {
-        Some(id) => id,
-        None => {
-            let mut context = Context::new();
-            context.insert("error", "Failed to join event");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+    let email = email.unwrap_or_default();
+    let participant_id = state
+        .add_participant(&event.id, name.clone(), email)
+        .map_err(|e| AppError::Conflict(e.to_string()))?;
 
     // Store participant ID in session
     let session_key = format!("participant_{}", event.id);
     let _ = session.insert(&session_key, participant_id.to_string());
 
+    let before = event;
+    let event = state.get_event(&before.id).ok_or(AppError::Internal)?;
+    let participant = event
+        .participants
+        .get(&participant_id)
+        .ok_or(AppError::Internal)?;
+    mailer.send_join_confirmation(&event, participant);
+    mailer.notify_changed_recipients(&before, &event);
+
     let mut context = Context::new();
-    context.insert("event", &state.get_event(&event.id).unwrap());
+    context.insert("event", &event);
     context.insert("participant_name", &name);
+    insert_budget(&mut context, &event);
     context.insert("event_url", &format!("/event/{}/view", event.id));
-    render_template(&tera, "joined.html", &context)
+    context.insert("pin", &participant.pin);
+    context.insert("recovery_url", &event.recovery_path(participant));
+    Ok(render_template(&pages, "joined.html", &context))
 }
 
-#[get("/event/{event_id}/manage/{organizer_token}")]
-pub async fn manage_event(
-    path: web::Path<(String, String)>,
-    state: web::Data<AppState>,
-    tera: web::Data<Tera>,
-) -> HttpResponse {
-    let (event_id_str, org_token_str) = path.into_inner();
-    
-    let event_id = match Uuid::parse_str(&event_id_str) {
-        Ok(id) => id,
-        Err(_) => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid event ID");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+// Empty input means "no date".
+fn parse_date(input: &str) -> Result<Option<NaiveDate>, &'static str> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    NaiveDate::parse_from_str(input, "%Y-%m-%d")
+        .map(Some)
+        .map_err(|_| "Invalid date, please use YYYY-MM-DD")
+}
 
-    let org_token = match Uuid::parse_str(&org_token_str) {
-        Ok(t) => t,
-        Err(_) => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid organizer token");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+// Parses the value of a datetime-local input, which is in the server's time
+// zone. Empty input means "no deadline".
+fn parse_local_datetime(input: &str) -> Result<Option<DateTime<Utc>>, &'static str> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M")
+        .ok()
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .map(|local| Some(local.with_timezone(&Utc)))
+        .ok_or("Invalid date and time, please use YYYY-MM-DDTHH:MM")
+}
 
-    let event = match state.get_event(&event_id) {
-        Some(e) => e,
-        None => {
-            let mut context = Context::new();
-            context.insert("error", "Event not found");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+// The budget for templates/budget.html, which words it in the page's language.
+fn insert_budget(context: &mut Context, event: &WichtelEvent) {
+    if let Some(budget) = &event.budget {
+        context.insert("budget", budget);
+    }
+}
+
+fn format_local(datetime: DateTime<Utc>, format: &str) -> String {
+    datetime.with_timezone(&Local).format(format).to_string()
+}
+
+// Like `render_template`, but with the status of a failed form submission.
+fn render_failed(pages: &Pages, template: &str, context: &Context, status: StatusCode) -> HttpResponse {
+    let mut response = render_template(pages, template, context);
+    if response.status().is_success() {
+        *response.status_mut() = status;
+    }
+    response
+}
+
+fn parse_event_id(event_id_str: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(event_id_str).map_err(|_| AppError::BadRequest("Invalid event ID".to_string()))
+}
+
+fn load_event(state: &AppState, event_id_str: &str) -> Result<WichtelEvent, AppError> {
+    let event_id = parse_event_id(event_id_str)?;
+    state
+        .get_event(&event_id)
+        .ok_or(AppError::NotFound("Event not found"))
+}
+
+// Resolves the event for an organizer URL.
+fn load_organizer_event(
+    state: &AppState,
+    event_id_str: &str,
+    org_token_str: &str,
+) -> Result<WichtelEvent, AppError> {
+    let event_id = parse_event_id(event_id_str)?;
+    let org_token = Uuid::parse_str(org_token_str)
+        .map_err(|_| AppError::Forbidden("Invalid organizer token"))?;
+
+    let event = state
+        .get_event(&event_id)
+        .ok_or(AppError::NotFound("Event not found"))?;
 
     if event.organizer_token != org_token {
-        let mut context = Context::new();
-        context.insert("error", "Invalid organizer token");
-        return render_template(&tera, "error.html", &context);
+        return Err(AppError::Forbidden("Invalid organizer token"));
     }
+    Ok(event)
+}
+
+// Shows the manage page, with a 400 status if it reports an error.
+fn render_manage(pages: &Pages, event: &WichtelEvent, error: Option<&str>) -> HttpResponse {
+    let pages = &pages.for_event(event);
+    let mut context = manage_context(event);
+    match error {
+        Some(error) => {
+            context.insert("error", error);
+            render_failed(pages, "manage.html", &context, StatusCode::BAD_REQUEST)
+        }
+        None => render_template(pages, "manage.html", &context),
+    }
+}
+
+fn manage_context(event: &WichtelEvent) -> Context {
+    let mut participants: Vec<&Participant> = event.participants.values().collect();
+    participants.sort_by_key(|p| p.name.to_lowercase());
 
     let mut context = Context::new();
-    context.insert("event", &event);
-    context.insert("organizer_token", &org_token_str);
+    context.insert("event", event);
+    context.insert("participants", &participants);
+    context.insert("organizer_token", &event.organizer_token.to_string());
     context.insert("invite_url", &format!("/join/{}", event.invite_code));
-    context.insert("can_close", &(event.participants.len() >= 2));
-    render_template(&tera, "manage.html", &context)
+    context.insert("can_close", &(event.participants.len() > event.gifts_per_person));
+    context.insert("max_gifts_per_person", &MAX_GIFTS_PER_PERSON);
+    if event.status == EventStatus::Closed && event.history_years > 0 {
+        context.insert("repeated_pairs", &event.repeated_pairs());
+    }
+    context.insert("likely_duplicates", &event.likely_duplicates());
+    context.insert("event_language", &event.language.map(Locale::code));
+    insert_budget(&mut context, event);
+    if let Some(deadline) = event.registration_deadline {
+        context.insert("deadline_input", &format_local(deadline, "%Y-%m-%dT%H:%M"));
+        context.insert("deadline_display", &format_local(deadline, "%d.%m.%Y %H:%M"));
+    }
+    if let Some(result) = &event.auto_close_result {
+        context.insert("auto_closed_at", &format_local(result.at, "%d.%m.%Y %H:%M"));
+    }
+    context.insert(
+        "email_count",
+        &event.participants.values().filter(|p| p.email.is_some()).count(),
+    );
+    if let Some(pending) = &event.draw_commitment {
+        context.insert("commitment_published_at", &format_local(pending.published_at, "%d.%m.%Y %H:%M"));
+    }
+    context
 }
 
-#[post("/event/{event_id}/close/{organizer_token}")]
-pub async fn close_event(
+// Gives a page reporting `error` the error's status instead of a plain 400.
+fn with_status(mut response: HttpResponse, error: &AppError) -> HttpResponse {
+    if response.status().is_client_error() {
+        *response.status_mut() = error.status_code();
+    }
+    response
+}
+
+fn redirect_to_manage(event: &WichtelEvent) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header((
+            "Location",
+            format!("/event/{}/manage/{}", event.id, event.organizer_token),
+        ))
+        .finish()
+}
+
+#[get("/event/{event_id}/manage/{organizer_token}")]
+pub async fn manage_event(
     path: web::Path<(String, String)>,
     state: web::Data<AppState>,
-    tera: web::Data<Tera>,
-) -> HttpResponse {
+    pages: Pages,
+) -> Result<HttpResponse> {
     let (event_id_str, org_token_str) = path.into_inner();
-    
-    let event_id = match Uuid::parse_str(&event_id_str) {
-        Ok(id) => id,
-        Err(_) => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid event ID");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
 
-    let org_token = match Uuid::parse_str(&org_token_str) {
-        Ok(t) => t,
-        Err(_) => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid organizer token");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    Ok(render_manage(&pages, &event, None))
+}
 
-    match state.close_event(&event_id, &org_token) {
-        Ok(_) => {
-            HttpResponse::Found()
-                .insert_header(("Location", format!("/event/{}/manage/{}", event_id, org_token)))
-                .finish()
-        }
-        Err(e) => {
-            let mut context = Context::new();
-            context.insert("error", e);
-            render_template(&tera, "error.html", &context)
-        }
-    }
+// Serves the QR code of the link to `path`.
+fn qr_image(qr_codes: &QrCodes, path: &str, format: &str) -> Result<HttpResponse, AppError> {
+    let format: QrFormat = format
+        .parse()
+        .map_err(|_| AppError::NotFound("Unknown image format"))?;
+    let image = qr_codes.render(path, format).map_err(|e| {
+        log::error!("Failed to render QR code: {}", e);
+        AppError::Internal
+    })?;
+    // Personal links are as good as a password, keep them out of caches.
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(image))
 }
 
-#[get("/event/{event_id}/view")]
-pub async fn view_assignment(
-    path: web::Path<String>,
-    session: Session,
+#[get("/event/{event_id}/qr/{organizer_token}/invite.{format}")]
+pub async fn invite_qr_code(
+    path: web::Path<(String, String, String)>,
     state: web::Data<AppState>,
-    tera: web::Data<Tera>,
-) -> HttpResponse {
-    let event_id_str = path.into_inner();
-    
-    let event_id = match Uuid::parse_str(&event_id_str) {
-        Ok(id) => id,
-        Err(_) => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid event ID");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+    qr_codes: web::Data<QrCodes>,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str, format) = path.into_inner();
 
-    let event = match state.get_event(&event_id) {
-        Some(e) => e,
-        None => {
-            let mut context = Context::new();
-            context.insert("error", "Event not found");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    Ok(qr_image(&qr_codes, &format!("/join/{}", event.invite_code), &format)?)
+}
 
-    // Try to get participant from session
-    let session_key = format!("participant_{}", event.id);
-    if let Ok(Some(participant_id_str)) = session.get::<String>(&session_key) {
-        if let Ok(participant_id) = Uuid::parse_str(&participant_id_str) {
-            if let Some(participant) = event.participants.get(&participant_id) {
-                let mut context = Context::new();
-                context.insert("event", &event);
-                context.insert("participant", participant);
-                
-                if event.status == EventStatus::Closed {
-                    if let Some(assigned) = event.get_assignment(participant_id) {
-                        context.insert("assigned_to", assigned);
-                    }
-                }
-                
-                return render_template(&tera, "view_assignment.html", &context);
-            }
-        }
-    }
+#[get("/event/{event_id}/qr/{organizer_token}/participants/{participant_id}.{format}")]
+pub async fn participant_qr_code(
+    path: web::Path<(String, String, String, String)>,
+    state: web::Data<AppState>,
+    qr_codes: web::Data<QrCodes>,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str, participant_id_str, format) = path.into_inner();
 
-    // No cookie - redirect to identity page
-    HttpResponse::Found()
-        .insert_header(("Location", format!("/event/{}/identify", event_id)))
-        .finish()
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let participant = parse_participant_id(&participant_id_str)
+        .ok()
+        .and_then(|id| event.participants.get(&id))
+        .ok_or(AppError::NotFound("Participant not found in this event"))?;
+    Ok(qr_image(&qr_codes, &event.recovery_path(participant), &format)?)
 }
 
-#[get("/event/{event_id}/identify")]
-pub async fn identify_page(
-    path: web::Path<String>,
+// Printable sheet with the invite code and everyone's personal code, for
+// handing out at an office party.
+#[get("/event/{event_id}/qr/{organizer_token}")]
+pub async fn qr_code_sheet(
+    path: web::Path<(String, String)>,
     state: web::Data<AppState>,
-    tera: web::Data<Tera>,
-) -> HttpResponse {
-    let event_id_str = path.into_inner();
-    
-    let event_id = match Uuid::parse_str(&event_id_str) {
-        Ok(id) => id,
-        Err(_) => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid event ID");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
 
-    let event = match state.get_event(&event_id) {
-        Some(e) => e,
-        None => {
-            let mut context = Context::new();
-            context.insert("error", "Event not found");
-            return render_template(&tera, "error.html", &context);
-        }
-    };
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let pages = pages.for_event(&event);
+    let mut participants: Vec<&Participant> = event.participants.values().collect();
+    participants.sort_by_key(|p| p.name.to_lowercase());
 
     let mut context = Context::new();
     context.insert("event", &event);
-    render_template(&tera, "identify.html", &context)
+    context.insert("participants", &participants);
+    context.insert("organizer_token", &event.organizer_token.to_string());
+    context.insert("invite_url", &format!("/join/{}", event.invite_code));
+    Ok(render_template(&pages, "qr_codes.html", &context))
 }
 
-#[derive(Debug, Deserialize)]
-pub struct SearchQuery {
-    pub q: String,
+// What's printed on a participant's folded card.
+#[derive(Debug, Serialize)]
+struct FoldCard<'a> {
+    participant: &'a Participant,
+    recipients: Vec<&'a str>,
 }
 
-#[get("/event/{event_id}/search")]
-pub async fn search_participants(
-    path: web::Path<String>,
-    query: web::Query<SearchQuery>,
+// Sheet of cards for drawing at a meeting. Each card shows the participant's
+// name on the outside and their recipients inside, so whoever cuts and folds
+// them never needs to look at the pairs.
+#[get("/event/{event_id}/cards/{organizer_token}")]
+pub async fn fold_cards(
+    path: web::Path<(String, String)>,
     state: web::Data<AppState>,
+    pages: Pages,
 ) -> Result<HttpResponse> {
-    let event_id_str = path.into_inner();
-    
-    let event_id = match Uuid::parse_str(&event_id_str) {
-        Ok(id) => id,
-        Err(_) => return Ok(HttpResponse::BadRequest().json(Vec::<FuzzyMatch>::new())),
-    };
+    let (event_id_str, org_token_str) = path.into_inner();
 
-    let event = match state.get_event(&event_id) {
-        Some(e) => e,
-        None => return Ok(HttpResponse::NotFound().json(Vec::<FuzzyMatch>::new())),
-    };
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    if event.status != EventStatus::Closed {
+        return Err(AppError::Conflict("Names have not been drawn yet".to_string()).into());
+    }
+    let pages = pages.for_event(&event);
 
-    let matcher = SkimMatcherV2::default();
-    let search_term = query.q.to_lowercase();
-    
-    let mut matches: Vec<FuzzyMatch> = event.participants
+    let mut cards: Vec<FoldCard> = event
+        .participants
         .values()
-        .filter_map(|p| {
-            matcher.fuzzy_match(&p.name.to_lowercase(), &search_term)
-                .map(|score| FuzzyMatch {
-                    id: p.id.to_string(),
-                    name: p.name.clone(),
-                    score,
-                })
+        .map(|participant| FoldCard {
+            participant,
+            recipients: event
+                .recipients_of(participant.id)
+                .into_iter()
+                .map(|r| r.name.as_str())
+                .collect(),
         })
         .collect();
-    
-    matches.sort_by(|a, b| b.score.cmp(&a.score));
-    matches.truncate(5);
+    cards.sort_by_key(|c| c.participant.name.to_lowercase());
 
-    Ok(HttpResponse::Ok().json(matches))
+    let mut context = Context::new();
+    context.insert("event", &event);
+    context.insert("cards", &cards);
+    context.insert("organizer_token", &event.organizer_token.to_string());
+    insert_budget(&mut context, &event);
+    Ok(render_template(&pages, "fold_cards.html", &context))
 }
 
-#[post("/event/{event_id}/confirm-identity")]
-pub async fn confirm_identity(
+// Sends `body` as a file to save rather than a page to show. Exports hold
+// personal data, so they are kept out of caches.
+fn download(content_type: &str, filename: &str, body: impl Into<web::Bytes>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(body.into())
+}
+
+#[get("/event/{event_id}/export/{organizer_token}/event.json")]
+pub async fn export_event(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let json = export::to_json(&event)?;
+    let filename = format!("wichtel-{}.json", event.invite_code);
+    Ok(download("application/json", &filename, json))
+}
+
+#[get("/event/{event_id}/export/{organizer_token}/participants.csv")]
+pub async fn export_participants(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let csv = export::participants_csv(&event, false)?;
+    let filename = format!("wichtel-{}-participants.csv", event.invite_code);
+    Ok(download("text/csv; charset=utf-8", &filename, csv))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevealForm {
+    pub reveal: Option<String>,
+}
+
+// Same list with who draws whom, which the organizer has to ask for
+// explicitly as it spoils the surprise for anyone looking at the file.
+#[post("/event/{event_id}/export/{organizer_token}/participants.csv")]
+pub async fn export_assignments(
+    path: web::Path<(String, String)>,
+    form: web::Form<RevealForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    if form.reveal.is_none() {
+        return Ok(render_manage(
+            &pages,
+            &event,
+            Some("Confirm that you want to see who draws whom"),
+        ));
+    }
+    let csv = export::participants_csv(&event, true)?;
+    let filename = format!("wichtel-{}-assignments.csv", event.invite_code);
+    Ok(download("text/csv; charset=utf-8", &filename, csv))
+}
+
+#[post("/event/{event_id}/close/{organizer_token}")]
+pub async fn close_event(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+    mailer: web::Data<Mailer>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.close_event(&event.id, &event.organizer_token) {
+        Ok(_) => {
+            if let Some(closed) = state.get_event(&event.id) {
+                mailer.send_draw_notifications(&closed);
+            }
+            redirect_to_manage(&event)
+        }
+        Err(e) => with_status(render_manage(&pages, &event, Some(&e.to_string())), &e),
+    })
+}
+
+#[post("/event/{event_id}/remind/{organizer_token}")]
+pub async fn send_reminders(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+    mailer: web::Data<Mailer>,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    mailer.send_reminders(&event);
+    Ok(redirect_to_manage(&event))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExchangeDateForm {
+    pub exchange_date: String,
+}
+
+#[post("/event/{event_id}/exchange-date/{organizer_token}")]
+pub async fn set_exchange_date(
+    path: web::Path<(String, String)>,
+    form: web::Form<ExchangeDateForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    let date = match parse_date(&form.exchange_date) {
+        Ok(d) => d,
+        Err(e) => return Ok(render_manage(&pages, &event, Some(e))),
+    };
+
+    Ok(match state.set_exchange_date(&event.id, &event.organizer_token, date) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[post("/event/{event_id}/budget/{organizer_token}")]
+pub async fn set_budget(
+    path: web::Path<(String, String)>,
+    form: web::Form<BudgetForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    let budget = match form.parse() {
+        Ok(b) => b,
+        Err(e) => return Ok(render_manage(&pages, &event, Some(e))),
+    };
+
+    Ok(match state.set_budget(&event.id, &event.organizer_token, budget) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistrationDeadlineForm {
+    pub registration_deadline: String,
+}
+
+#[post("/event/{event_id}/deadline/{organizer_token}")]
+pub async fn set_registration_deadline(
+    path: web::Path<(String, String)>,
+    form: web::Form<RegistrationDeadlineForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    let deadline = match parse_local_datetime(&form.registration_deadline) {
+        Ok(d) => d,
+        Err(e) => return Ok(render_manage(&pages, &event, Some(e))),
+    };
+
+    Ok(match state.set_registration_deadline(&event.id, &event.organizer_token, deadline) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DrawModeForm {
+    pub draw_mode: DrawMode,
+}
+
+#[post("/event/{event_id}/draw-mode/{organizer_token}")]
+pub async fn set_draw_mode(
+    path: web::Path<(String, String)>,
+    form: web::Form<DrawModeForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.set_draw_mode(&event.id, &event.organizer_token, form.draw_mode) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GiftsPerPersonForm {
+    pub gifts_per_person: usize,
+}
+
+#[post("/event/{event_id}/gifts/{organizer_token}")]
+pub async fn set_gifts_per_person(
+    path: web::Path<(String, String)>,
+    form: web::Form<GiftsPerPersonForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.set_gifts_per_person(&event.id, &event.organizer_token, form.gifts_per_person) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CloneEventForm {
+    pub name: String,
+}
+
+#[post("/event/{event_id}/clone/{organizer_token}")]
+pub async fn clone_event(
+    path: web::Path<(String, String)>,
+    form: web::Form<CloneEventForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.clone_event(&event.id, &event.organizer_token, &form.name) {
+        Ok(next) => redirect_to_manage(&next),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryForm {
+    pub history_years: usize,
+    pub history_constraint: HistoryConstraint,
+}
+
+#[post("/event/{event_id}/history/{organizer_token}")]
+pub async fn set_history(
+    path: web::Path<(String, String)>,
+    form: web::Form<HistoryForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.set_history(
+        &event.id,
+        &event.organizer_token,
+        form.history_years,
+        form.history_constraint,
+    ) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LateJoinForm {
+    pub late_join: Option<String>,
+}
+
+#[post("/event/{event_id}/late-join/{organizer_token}")]
+pub async fn set_late_join(
+    path: web::Path<(String, String)>,
+    form: web::Form<LateJoinForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.set_late_join(&event.id, &event.organizer_token, form.late_join.is_some()) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LanguageForm {
+    #[serde(default)]
+    pub language: String,
+}
+
+#[post("/event/{event_id}/language/{organizer_token}")]
+pub async fn set_event_language(
+    path: web::Path<(String, String)>,
+    form: web::Form<LanguageForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    // Anything but a known language code means "the visitor's browser language".
+    let language = Locale::from_code(&form.language);
+    Ok(match state.set_language(&event.id, &event.organizer_token, language) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[post("/event/{event_id}/commitment/{organizer_token}")]
+pub async fn publish_commitment(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.publish_commitment(&event.id, &event.organizer_token) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[post("/event/{event_id}/commitment/{organizer_token}/withdraw")]
+pub async fn withdraw_commitment(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.withdraw_commitment(&event.id, &event.organizer_token) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[post("/event/{event_id}/reveal-seed/{organizer_token}")]
+pub async fn reveal_seed(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    Ok(match state.reveal_seed(&event.id, &event.organizer_token) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PairExclusionForm {
+    pub giver: String,
+    pub receiver: String,
+    pub mutual: Option<String>,
+}
+
+#[post("/event/{event_id}/exclusions/{organizer_token}/pair")]
+pub async fn add_pair_exclusion(
+    path: web::Path<(String, String)>,
+    form: web::Form<PairExclusionForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    let (giver, receiver) = match (Uuid::parse_str(&form.giver), Uuid::parse_str(&form.receiver)) {
+        (Ok(g), Ok(r)) => (g, r),
+        _ => return Ok(render_manage(&pages, &event, Some("Invalid participant ID"))),
+    };
+
+    let exclusion = Exclusion::Pair {
+        id: Uuid::new_v4(),
+        giver,
+        receiver,
+        mutual: form.mutual.is_some(),
+    };
+    Ok(match state.add_exclusion(&event.id, &event.organizer_token, exclusion) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+// Checkbox lists submit one `member` field per selected participant, which
+// a plain struct cannot hold, so the form is read as raw pairs.
+#[post("/event/{event_id}/exclusions/{organizer_token}/group")]
+pub async fn add_group_exclusion(
+    path: web::Path<(String, String)>,
+    form: web::Form<Vec<(String, String)>>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    let mut label = String::new();
+    let mut members = Vec::new();
+    for (key, value) in form.into_inner() {
+        match key.as_str() {
+            "label" => label = value.trim().to_string(),
+            "member" => match Uuid::parse_str(&value) {
+                Ok(id) if !members.contains(&id) => members.push(id),
+                Ok(_) => {}
+                Err(_) => return Ok(render_manage(&pages, &event, Some("Invalid participant ID"))),
+            },
+            _ => {}
+        }
+    }
+    if label.is_empty() {
+        label = "Group".to_string();
+    }
+
+    let exclusion = Exclusion::Group {
+        id: Uuid::new_v4(),
+        label,
+        members,
+    };
+    Ok(match state.add_exclusion(&event.id, &event.organizer_token, exclusion) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+// Parses a participant id from an organizer form.
+fn parse_participant_id(id: &str) -> Result<Uuid, &'static str> {
+    Uuid::parse_str(id).map_err(|_| "Participant not found in this event")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameParticipantForm {
+    pub name: String,
+}
+
+#[post("/event/{event_id}/participants/{organizer_token}/{participant_id}/rename")]
+pub async fn rename_participant(
+    path: web::Path<(String, String, String)>,
+    form: web::Form<RenameParticipantForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str, participant_id_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let participant_id = match parse_participant_id(&participant_id_str) {
+        Ok(id) => id,
+        Err(e) => return Ok(render_manage(&pages, &event, Some(e))),
+    };
+
+    Ok(match state.rename_participant(&event.id, &event.organizer_token, participant_id, &form.name) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[post("/event/{event_id}/participants/{organizer_token}/{participant_id}/delete")]
+pub async fn remove_participant(
+    path: web::Path<(String, String, String)>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str, participant_id_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let participant_id = match parse_participant_id(&participant_id_str) {
+        Ok(id) => id,
+        Err(e) => return Ok(render_manage(&pages, &event, Some(e))),
+    };
+
+    Ok(match state.remove_participant(&event.id, &event.organizer_token, participant_id) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[post("/event/{event_id}/participants/{organizer_token}/{participant_id}/repair")]
+pub async fn remove_and_repair(
+    path: web::Path<(String, String, String)>,
+    state: web::Data<AppState>,
+    mailer: web::Data<Mailer>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str, participant_id_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let participant_id = match parse_participant_id(&participant_id_str) {
+        Ok(id) => id,
+        Err(e) => return Ok(render_manage(&pages, &event, Some(e))),
+    };
+
+    Ok(match state.remove_and_repair(&event.id, &event.organizer_token, participant_id) {
+        Ok(_) => {
+            if let Some(after) = state.get_event(&event.id) {
+                mailer.notify_changed_recipients(&event, &after);
+            }
+            redirect_to_manage(&event)
+        }
+        Err(e) => with_status(render_manage(&pages, &event, Some(&e.to_string())), &e),
+    })
+}
+
+#[post("/event/{event_id}/participants/{organizer_token}/{participant_id}/redraw")]
+pub async fn remove_and_redraw(
+    path: web::Path<(String, String, String)>,
+    state: web::Data<AppState>,
+    mailer: web::Data<Mailer>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str, participant_id_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let participant_id = match parse_participant_id(&participant_id_str) {
+        Ok(id) => id,
+        Err(e) => return Ok(render_manage(&pages, &event, Some(e))),
+    };
+
+    Ok(match state.remove_and_redraw(&event.id, &event.organizer_token, participant_id) {
+        Ok(_) => {
+            if let Some(after) = state.get_event(&event.id) {
+                mailer.notify_changed_recipients(&event, &after);
+            }
+            redirect_to_manage(&event)
+        }
+        Err(e) => with_status(render_manage(&pages, &event, Some(&e.to_string())), &e),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeParticipantsForm {
+    pub keep: String,
+    pub duplicate: String,
+}
+
+#[post("/event/{event_id}/participants/{organizer_token}/merge")]
+pub async fn merge_participants(
+    path: web::Path<(String, String)>,
+    form: web::Form<MergeParticipantsForm>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let (keep, duplicate) = match (
+        parse_participant_id(&form.keep),
+        parse_participant_id(&form.duplicate),
+    ) {
+        (Ok(keep), Ok(duplicate)) => (keep, duplicate),
+        (Err(e), _) | (_, Err(e)) => return Ok(render_manage(&pages, &event, Some(e))),
+    };
+
+    Ok(match state.merge_participants(&event.id, &event.organizer_token, keep, duplicate) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportParticipantsForm {
+    pub participants: String,
+}
+
+// A freshly imported participant with the link the organizer passes on.
+#[derive(Debug, Serialize)]
+struct ImportedParticipant<'a> {
+    participant: &'a Participant,
+    recovery_url: String,
+}
+
+#[post("/event/{event_id}/participants/{organizer_token}/import")]
+pub async fn import_participants(
+    path: web::Path<(String, String)>,
+    form: web::Form<ImportParticipantsForm>,
+    state: web::Data<AppState>,
+    mailer: web::Data<Mailer>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+    let pages = pages.for_event(&event);
+    let imported = import::parse(&form.participants)
+        .and_then(|rows| state.import_participants(&event.id, &event.organizer_token, rows));
+    let ids = match imported {
+        Ok(ids) => ids,
+        // There is no import form to show the error next to.
+        Err(e) if event.status == EventStatus::Closed => {
+            return Ok(render_manage(&pages, &event, Some(e.message)));
+        }
+        Err(e) => {
+            // Keeps the list so only the reported line needs fixing.
+            let mut context = manage_context(&event);
+            context.insert("import_error", &e);
+            context.insert("import_text", &form.participants);
+            return Ok(render_failed(&pages, "manage.html", &context, StatusCode::BAD_REQUEST));
+        }
+    };
+
+    let event = state.get_event(&event.id).ok_or(AppError::Internal)?;
+    let imported: Vec<ImportedParticipant> = ids
+        .iter()
+        .map(|id| {
+            let participant = event.participants.get(id).ok_or(AppError::Internal)?;
+            mailer.send_join_confirmation(&event, participant);
+            Ok(ImportedParticipant {
+                participant,
+                recovery_url: event.recovery_path(participant),
+            })
+        })
+        .collect::<Result<_, AppError>>()?;
+
+    let mut context = Context::new();
+    context.insert("event", &event);
+    context.insert("organizer_token", &event.organizer_token.to_string());
+    context.insert("imported", &imported);
+    Ok(render_template(&pages, "imported.html", &context))
+}
+
+#[post("/event/{event_id}/exclusions/{organizer_token}/{exclusion_id}/delete")]
+pub async fn remove_exclusion(
+    path: web::Path<(String, String, String)>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let (event_id_str, org_token_str, exclusion_id_str) = path.into_inner();
+
+    let event = load_organizer_event(&state, &event_id_str, &org_token_str)?;
+
+    let exclusion_id = match Uuid::parse_str(&exclusion_id_str) {
+        Ok(id) => id,
+        Err(_) => return Ok(render_manage(&pages, &event, Some("Exclusion not found"))),
+    };
+
+    Ok(match state.remove_exclusion(&event.id, &event.organizer_token, exclusion_id) {
+        Ok(_) => redirect_to_manage(&event),
+        Err(e) => render_manage(&pages, &event, Some(e)),
+    })
+}
+
+#[get("/event/{event_id}/view")]
+pub async fn view_assignment(
     path: web::Path<String>,
-    form: web::Form<ConfirmIdentityForm>,
     session: Session,
     state: web::Data<AppState>,
-    tera: web::Data<Tera>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let event = load_event(&state, &path)?;
+
+    Ok(match session_participant(&session, &event) {
+        Some(participant_id) => render_view(&pages, &event, participant_id, None),
+        // No cookie - redirect to identity page
+        None => HttpResponse::Found()
+            .insert_header(("Location", format!("/event/{}/identify", event.id)))
+            .finish(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct VerifiedPair<'a> {
+    // None if they are no longer taking part.
+    drawn_name: Option<&'a str>,
+    proof: PairProof,
+}
+
+// Public audit page of the draw. Shows the commitment and, to a participant
+// who identified in this browser, the proof for their own pair only.
+#[get("/event/{event_id}/verify")]
+pub async fn verify_draw(
+    path: web::Path<String>,
+    session: Session,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let event = load_event(&state, &path)?;
+    let pages = pages.for_event(&event);
+    let timestamp = |at: DateTime<Utc>| format_local(at, "%d.%m.%Y %H:%M:%S");
+
+    let mut context = Context::new();
+    context.insert("event_name", &event.name);
+    context.insert("event_id", &event.id.to_string());
+    if let Some(pending) = &event.draw_commitment {
+        context.insert("pending_commitment", &pending.commitment);
+        context.insert("pending_published_at", &timestamp(pending.published_at));
+    }
+    let withdrawn: Vec<(String, String, String)> = event
+        .withdrawn_commitments
+        .iter()
+        .map(|w| (w.commitment.clone(), timestamp(w.published_at), timestamp(w.withdrawn_at)))
+        .collect();
+    context.insert("withdrawn", &withdrawn);
+
+    if let Some(record) = &event.draw_record {
+        context.insert("commitment", &record.commitment);
+        context.insert("committed_at", &record.committed_at.map(timestamp));
+        context.insert("drawn_at", &timestamp(record.drawn_at));
+        context.insert("pairs_root", &record.pairs_root);
+        context.insert("amended_at", &record.amended_at.map(timestamp));
+        if let Some(revealed_at) = record.seed_revealed_at {
+            context.insert("seed", &record.seed);
+            context.insert("seed_revealed_at", &timestamp(revealed_at));
+            context.insert("draw_input", &record.input);
+            context.insert("commitment_matches", &record.commitment_matches());
+            context.insert("root_matches", &record.root_matches());
+        }
+
+        if let Some(participant_id) = session_participant(&session, &event) {
+            let participant = &event.participants[&participant_id];
+            context.insert("participant_name", &participant.name);
+            let proofs: Vec<VerifiedPair> = record
+                .pair_proofs(participant_id)
+                .into_iter()
+                .map(|proof| VerifiedPair {
+                    drawn_name: event.participants.get(&proof.receiver).map(|p| p.name.as_str()),
+                    proof,
+                })
+                .collect();
+            let mut drawn: Vec<Uuid> = proofs.iter().map(|p| p.proof.receiver).collect();
+            let mut current = participant.assigned_to.clone();
+            drawn.sort();
+            current.sort();
+            context.insert("recipient_changed", &(!proofs.is_empty() && drawn != current));
+            context.insert("proofs", &proofs);
+        }
+    }
+
+    Ok(render_template(&pages, "verify.html", &context))
+}
+
+// The participant of `event` this browser has identified as, if any. Sessions
+// of merged participants are moved over to the entry they were merged into,
+// those of removed participants are dropped.
+fn session_participant(session: &Session, event: &WichtelEvent) -> Option<Uuid> {
+    let session_key = format!("participant_{}", event.id);
+    let participant_id = session.get::<String>(&session_key).ok()??;
+    let participant_id = Uuid::parse_str(&participant_id).ok()?;
+    if event.participants.contains_key(&participant_id) {
+        return Some(participant_id);
+    }
+
+    match event.merged_participants.get(&participant_id) {
+        Some(&merged_into) => {
+            let _ = session.insert(&session_key, merged_into.to_string());
+            Some(merged_into)
+        }
+        None => {
+            session.remove(&session_key);
+            None
+        }
+    }
+}
+
+// A message as shown to one side of a thread. Deliberately carries no
+// participant ids so the giver's identity cannot leak into the page.
+#[derive(Debug, Serialize)]
+struct ThreadMessage<'a> {
+    from_me: bool,
+    body: &'a str,
+    sent_at: DateTime<Utc>,
+}
+
+fn thread_context(
+    event: &WichtelEvent,
+    participant_id: Uuid,
+    thread: MessageThread,
+    position: usize,
+) -> Vec<ThreadMessage<'_>> {
+    let mine = match thread {
+        MessageThread::Recipient => MessageAuthor::Giver,
+        MessageThread::Wichtel => MessageAuthor::Receiver,
+    };
+    event
+        .thread_messages(participant_id, thread, position)
+        .into_iter()
+        .map(|m| ThreadMessage {
+            from_me: m.author == mine,
+            body: &m.body,
+            sent_at: m.sent_at,
+        })
+        .collect()
+}
+
+// One of the people a participant is buying a gift for.
+#[derive(Debug, Serialize)]
+struct RecipientView<'a> {
+    position: usize,
+    participant: &'a Participant,
+    has_wishlist: bool,
+    messages: Vec<ThreadMessage<'a>>,
+}
+
+fn render_view(
+    pages: &Pages,
+    event: &WichtelEvent,
+    participant_id: Uuid,
+    error: Option<&str>,
 ) -> HttpResponse {
-    let event_id_str = path.into_inner();
-    
-    let event_id = match Uuid::parse_str(&event_id_str) {
-        Ok(id) => id,
-        Err(_) => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid event ID");
-            return render_template(&tera, "error.html", &context);
+    let participant = &event.participants[&participant_id];
+    let pages = &pages.for_event(event);
+
+    let mut context = Context::new();
+    context.insert("event", event);
+    context.insert("participant", participant);
+    context.insert("recovery_url", &event.recovery_path(participant));
+    context.insert("wishlist_editable", &event.wishlist_editable());
+    insert_budget(&mut context, event);
+    if let Some(error) = error {
+        context.insert("error", error);
+    }
+    let status = match error {
+        Some(_) => StatusCode::BAD_REQUEST,
+        None => StatusCode::OK,
+    };
+
+    if event.status == EventStatus::Closed {
+        let recipients: Vec<RecipientView> = event
+            .recipients_of(participant_id)
+            .into_iter()
+            .enumerate()
+            .map(|(position, recipient)| RecipientView {
+                position,
+                participant: recipient,
+                has_wishlist: !recipient.wishlist.is_empty(),
+                messages: thread_context(event, participant_id, MessageThread::Recipient, position),
+            })
+            .collect();
+        context.insert("recipients", &recipients);
+
+        // Only the number of Wichtels is shown, never who they are
+        let wichtel_threads: Vec<Vec<ThreadMessage>> = (0..event.givers_of(participant_id).len())
+            .map(|position| thread_context(event, participant_id, MessageThread::Wichtel, position))
+            .collect();
+        context.insert("wichtel_threads", &wichtel_threads);
+    }
+
+    render_failed(pages, "view_assignment.html", &context, status)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WishlistForm {
+    #[serde(default)]
+    pub items: String,
+    #[serde(default)]
+    pub sizes: String,
+    #[serde(default)]
+    pub dislikes: String,
+}
+
+#[post("/event/{event_id}/wishlist")]
+pub async fn update_wishlist(
+    path: web::Path<String>,
+    form: web::Form<WishlistForm>,
+    session: Session,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let event = load_event(&state, &path)?;
+
+    let participant_id = match session_participant(&session, &event) {
+        Some(id) => id,
+        None => {
+            return Ok(HttpResponse::Found()
+                .insert_header(("Location", format!("/event/{}/identify", event.id)))
+                .finish())
         }
     };
 
-    let participant_id = match Uuid::parse_str(&form.participant_id) {
-        Ok(id) => id,
-        Err(_) => {
-            let mut context = Context::new();
-            context.insert("error", "Invalid participant ID");
-            return render_template(&tera, "error.html", &context);
+    let wishlist = Wishlist {
+        items: Wishlist::parse_items(&form.items),
+        sizes: form.sizes.trim().to_string(),
+        dislikes: form.dislikes.trim().to_string(),
+        updated_at: None,
+    };
+
+    Ok(match state.update_wishlist(&event.id, participant_id, wishlist) {
+        Ok(_) => HttpResponse::Found()
+            .insert_header(("Location", format!("/event/{}/view#wishlist", event.id)))
+            .finish(),
+        Err(e) => render_view(&pages, &event, participant_id, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailForm {
+    #[serde(default)]
+    pub email: String,
+}
+
+#[post("/event/{event_id}/email")]
+pub async fn update_email(
+    path: web::Path<String>,
+    form: web::Form<EmailForm>,
+    session: Session,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let event = load_event(&state, &path)?;
+
+    let participant_id = match session_participant(&session, &event) {
+        Some(id) => id,
+        None => {
+            return Ok(HttpResponse::Found()
+                .insert_header(("Location", format!("/event/{}/identify", event.id)))
+                .finish())
         }
     };
 
-    let event = match state.get_event(&event_id) {
-        Some(e) => e,
+    let result = mailer::parse_email(&form.email)
+        .and_then(|email| state.set_email(&event.id, participant_id, email));
+    Ok(match result {
+        Ok(_) => HttpResponse::Found()
+            .insert_header(("Location", format!("/event/{}/view#access", event.id)))
+            .finish(),
+        Err(e) => render_view(&pages, &event, participant_id, Some(e)),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageForm {
+    pub thread: MessageThread,
+    /// Which recipient or Wichtel, if there are several.
+    #[serde(default)]
+    pub position: usize,
+    pub body: String,
+}
+
+#[post("/event/{event_id}/messages")]
+pub async fn send_message(
+    path: web::Path<String>,
+    form: web::Form<MessageForm>,
+    session: Session,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let event = load_event(&state, &path)?;
+
+    let participant_id = match session_participant(&session, &event) {
+        Some(id) => id,
         None => {
-            let mut context = Context::new();
-            context.insert("error", "Event not found");
-            return render_template(&tera, "error.html", &context);
+            return Ok(HttpResponse::Found()
+                .insert_header(("Location", format!("/event/{}/identify", event.id)))
+                .finish())
         }
     };
 
-    if !event.participants.contains_key(&participant_id) {
+    let anchor = match form.thread {
+        MessageThread::Recipient => format!("messages-recipient-{}", form.position),
+        MessageThread::Wichtel => format!("messages-wichtel-{}", form.position),
+    };
+    Ok(match state.send_message(&event.id, participant_id, form.thread, form.position, &form.body) {
+        Ok(_) => HttpResponse::Found()
+            .insert_header(("Location", format!("/event/{}/view#{}", event.id, anchor)))
+            .finish(),
+        Err(e) => render_view(&pages, &event, participant_id, Some(e)),
+    })
+}
+
+#[get("/event/{event_id}/identify")]
+pub async fn identify_page(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let event = load_event(&state, &path)?;
+    let pages = pages.for_event(&event);
+
+    let mut context = Context::new();
+    context.insert("event", &event);
+    Ok(render_template(&pages, "identify.html", &context))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+}
+
+#[get("/event/{event_id}/search")]
+pub async fn search_participants(
+    path: web::Path<String>,
+    query: web::Query<SearchQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let event_id_str = path.into_inner();
+    
+    let event_id = match Uuid::parse_str(&event_id_str) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(Vec::<FuzzyMatch>::new())),
+    };
+
+    let event = match state.get_event(&event_id) {
+        Some(e) => e,
+        None => return Ok(HttpResponse::NotFound().json(Vec::<FuzzyMatch>::new())),
+    };
+
+    let matcher = SkimMatcherV2::default();
+    let search_term = query.q.to_lowercase();
+    
+    let mut matches: Vec<FuzzyMatch> = event.participants
+        .values()
+        .filter_map(|p| {
+            matcher.fuzzy_match(&p.name.to_lowercase(), &search_term)
+                .map(|score| FuzzyMatch {
+                    id: p.id.to_string(),
+                    name: p.name.clone(),
+                    score,
+                })
+        })
+        .collect();
+    
+    matches.sort_by_key(|m| std::cmp::Reverse(m.score));
+    matches.truncate(5);
+
+    Ok(HttpResponse::Ok().json(matches))
+}
+
+#[post("/event/{event_id}/confirm-identity")]
+pub async fn confirm_identity(
+    path: web::Path<String>,
+    form: web::Form<ConfirmIdentityForm>,
+    session: Session,
+    state: web::Data<AppState>,
+    pages: Pages,
+) -> Result<HttpResponse> {
+    let event = load_event(&state, &path)?;
+    let participant_id = Uuid::parse_str(&form.participant_id)
+        .map_err(|_| AppError::BadRequest("Invalid participant ID".to_string()))?;
+
+    if let Err(e) = state.verify_pin(&event, &participant_id, &form.pin) {
+        let pages = pages.for_event(&event);
         let mut context = Context::new();
-        context.insert("error", "Participant not found in this event");
-        return render_template(&tera, "error.html", &context);
+        context.insert("event", &event);
+        context.insert("error", e);
+        return Ok(render_failed(&pages, "identify.html", &context, StatusCode::BAD_REQUEST));
     }
 
     // Store participant ID in session
     let session_key = format!("participant_{}", event.id);
     let _ = session.insert(&session_key, participant_id.to_string());
 
-    HttpResponse::Found()
-        .insert_header(("Location", format!("/event/{}/view", event_id)))
-        .finish()
+    Ok(HttpResponse::Found()
+        .insert_header(("Location", format!("/event/{}/view", event.id)))
+        .finish())
+}
+
+#[get("/event/{event_id}/recover/{recovery_token}")]
+pub async fn recover_identity(
+    path: web::Path<(String, String)>,
+    session: Session,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (event_id_str, token_str) = path.into_inner();
+
+    let event = load_event(&state, &event_id_str)?;
+    let participant = Uuid::parse_str(&token_str)
+        .ok()
+        .and_then(|token| event.participant_by_recovery_token(&token))
+        .ok_or(AppError::NotFound("This personal link is not valid"))?;
+
+    let session_key = format!("participant_{}", event.id);
+    let _ = session.insert(&session_key, participant.id.to_string());
+
+    Ok(HttpResponse::Found()
+        .insert_header(("Location", format!("/event/{}/view", event.id)))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    async fn language_redirect(referer: &str) -> String {
+        let app = test::init_service(App::new().service(choose_language)).await;
+        let req = test::TestRequest::get()
+            .uri("/lang/de")
+            .insert_header((header::REFERER, referer))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        resp.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string()
+    }
+
+    #[actix_web::test]
+    async fn language_switch_returns_to_the_page() {
+        let back = language_redirect("https://wichtel.example/event/1/view?x=1").await;
+        assert_eq!(back, "/event/1/view?x=1");
+    }
+
+    #[actix_web::test]
+    async fn language_switch_stays_on_this_site() {
+        for referer in [
+            "https://wichtel.example//evil.example/",
+            "https://wichtel.example/\\evil.example/",
+            "not a url",
+        ] {
+            assert_eq!(language_redirect(referer).await, "/");
+        }
+    }
 }
@@ -0,0 +1,125 @@
+use actix_web::http::header::{AcceptLanguage, Header, Preference};
+use actix_web::HttpRequest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tera::{Tera, Value};
+
+pub const COOKIE_NAME: &str = "lang";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::De];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::De => "de",
+        }
+    }
+
+    /// Accepts language tags with a region too, so `de-AT` is German.
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split(['-', '_']).next()?.trim();
+        Locale::ALL
+            .into_iter()
+            .find(|l| l.code().eq_ignore_ascii_case(primary))
+    }
+}
+
+// Replaces `{name}` placeholders with the other arguments of the call.
+fn interpolate(text: &str, args: &HashMap<String, Value>) -> String {
+    let mut text = text.to_string();
+    for (name, value) in args.iter().filter(|(name, _)| *name != "msg") {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        text = text.replace(&format!("{{{}}}", name), &value);
+    }
+    text
+}
+
+/// The `t` function of the templates: `t(msg="Hello {name}", name=...)`.
+/// Catalogs are keyed by the English text, which is also what's shown when a
+/// message has no translation, e.g. the error messages naming participants.
+struct Translate {
+    catalog: HashMap<String, String>,
+}
+
+impl tera::Function for Translate {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let msg = args
+            .get("msg")
+            .and_then(Value::as_str)
+            .ok_or("t() needs a `msg` string")?;
+        let text = self.catalog.get(msg).map_or(msg, String::as_str);
+        Ok(Value::String(interpolate(text, args)))
+    }
+}
+
+/// The templates once per language, each with the `t` function for it.
+pub struct Templates {
+    by_locale: HashMap<Locale, Tera>,
+}
+
+impl Templates {
+    /// English is the language of the templates themselves, every other
+    /// language needs a catalog at `<locales_dir>/<code>.json`.
+    pub fn load(tera: Tera, locales_dir: &Path) -> Result<Self, String> {
+        let mut by_locale = HashMap::new();
+        for locale in Locale::ALL {
+            let catalog = match locale {
+                Locale::En => HashMap::new(),
+                _ => {
+                    let path = locales_dir.join(format!("{}.json", locale.code()));
+                    let content = fs::read_to_string(&path)
+                        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                    serde_json::from_str(&content)
+                        .map_err(|e| format!("Invalid catalog {}: {}", path.display(), e))?
+                }
+            };
+            let mut localized = tera.clone();
+            localized.register_function("t", Translate { catalog });
+            by_locale.insert(locale, localized);
+        }
+        Ok(Self { by_locale })
+    }
+
+    pub fn get(&self, locale: Locale) -> &Tera {
+        &self.by_locale[&locale]
+    }
+}
+
+/// The language picked with the language switch, if any.
+pub fn chosen_locale(req: &HttpRequest) -> Option<Locale> {
+    req.cookie(COOKIE_NAME)
+        .and_then(|c| Locale::from_code(c.value()))
+}
+
+/// The supported language the browser ranks highest.
+pub fn accepted_locale(req: &HttpRequest) -> Option<Locale> {
+    AcceptLanguage::parse(req)
+        .ok()?
+        .ranked()
+        .into_iter()
+        .find_map(|preference| match preference {
+            Preference::Specific(tag) => Locale::from_code(tag.primary_language()),
+            Preference::Any => None,
+        })
+}
+
+/// Language for pages that don't belong to an event.
+pub fn request_locale(req: &HttpRequest) -> Locale {
+    chosen_locale(req)
+        .or_else(|| accepted_locale(req))
+        .unwrap_or_default()
+}
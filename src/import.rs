@@ -0,0 +1,265 @@
+use csv::{Position, ReaderBuilder, Trim};
+use serde::Serialize;
+
+use crate::mailer;
+
+/// One person of an imported list.
+#[derive(Debug, Clone)]
+pub struct ImportRow {
+    pub line: usize,
+    pub name: String,
+    pub email: Option<String>,
+    pub group: Option<String>,
+    /// Names of people this one must neither draw nor be drawn by.
+    pub exclusions: Vec<String>,
+}
+
+/// Why a list could not be imported, with the offending line if there is one.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportError {
+    pub line: Option<usize>,
+    pub message: &'static str,
+    /// Fills the `{name}` placeholder of `message`.
+    pub name: String,
+}
+
+impl ImportError {
+    pub fn at(line: usize, message: &'static str, name: &str) -> Self {
+        Self {
+            line: Some(line),
+            message,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl From<&'static str> for ImportError {
+    fn from(message: &'static str) -> Self {
+        Self {
+            line: None,
+            message,
+            name: String::new(),
+        }
+    }
+}
+
+/// Reads either a CSV file with a header row naming its columns (`name`,
+/// `email`, `group`, `exclusions`, separated by commas, semicolons or tabs) or
+/// a plain list with one name per line, optionally as `Name <email>`.
+pub fn parse(input: &str) -> Result<Vec<ImportRow>, ImportError> {
+    let input = input.trim_start_matches('\u{feff}');
+    let first_line = input.lines().find(|line| !line.trim().is_empty());
+    let rows = match first_line.and_then(csv_delimiter) {
+        Some(delimiter) => parse_csv(input, delimiter)?,
+        None => parse_list(input)?,
+    };
+    if rows.is_empty() {
+        return Err("There is nobody on the list".into());
+    }
+    Ok(rows)
+}
+
+// The delimiter of a CSV header row, or None if `line` is no such row.
+fn csv_delimiter(line: &str) -> Option<u8> {
+    [b';', b'\t', b',']
+        .into_iter()
+        .find(|&d| line.contains(d as char))
+        .filter(|&d| {
+            line.split(d as char)
+                .any(|cell| column(cell) == Some(Column::Name))
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Name,
+    Email,
+    Group,
+    Exclusions,
+}
+
+fn column(header: &str) -> Option<Column> {
+    match header.trim().trim_matches('"').to_lowercase().as_str() {
+        "name" => Some(Column::Name),
+        "email" | "e-mail" | "mail" => Some(Column::Email),
+        "group" => Some(Column::Group),
+        "exclusions" | "exclude" => Some(Column::Exclusions),
+        _ => None,
+    }
+}
+
+fn parse_csv(input: &str, delimiter: u8) -> Result<Vec<ImportRow>, ImportError> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(input.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|_| "The CSV header could not be read")?
+        .clone();
+    let columns: Vec<Option<Column>> = headers.iter().map(column).collect();
+    let line_at = |position: &Position| line_number(input, position);
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| match e.position() {
+            Some(position) => ImportError::at(line_at(position), "This line is not valid CSV", ""),
+            None => "The CSV could not be read".into(),
+        })?;
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let line = record.position().map_or(0, line_at);
+        let field = |wanted: Column| {
+            columns
+                .iter()
+                .position(|&c| c == Some(wanted))
+                .and_then(|i| record.get(i))
+                .unwrap_or("")
+        };
+
+        let name = field(Column::Name);
+        if name.is_empty() {
+            return Err(ImportError::at(line, "Name cannot be empty", ""));
+        }
+        let email = mailer::parse_email(field(Column::Email))
+            .map_err(|e| ImportError::at(line, e, name))?;
+        let group = Some(field(Column::Group))
+            .filter(|g| !g.is_empty())
+            .map(str::to_string);
+        let exclusions = field(Column::Exclusions)
+            .split([',', ';'])
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(str::to_string)
+            .collect();
+        rows.push(ImportRow {
+            line,
+            name: name.to_string(),
+            email,
+            group,
+            exclusions,
+        });
+    }
+    Ok(rows)
+}
+
+// The reader skips blank lines without counting them, and a record's position
+// is where those blank lines start. Its line is the first one after them.
+fn line_number(input: &str, position: &Position) -> usize {
+    let before = &input[..position.byte() as usize];
+    let blank = input[before.len()..]
+        .chars()
+        .take_while(|c| matches!(c, '\r' | '\n'))
+        .filter(|&c| c == '\n')
+        .count();
+    before.matches('\n').count() + blank + 1
+}
+
+fn parse_list(input: &str) -> Result<Vec<ImportRow>, ImportError> {
+    let mut rows = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, email) = match line.strip_suffix('>').and_then(|l| l.rsplit_once('<')) {
+            Some((name, email)) => (name.trim(), email),
+            None => (line, ""),
+        };
+        if name.is_empty() {
+            return Err(ImportError::at(line_number, "Name cannot be empty", ""));
+        }
+        let email = mailer::parse_email(email).map_err(|e| ImportError::at(line_number, e, name))?;
+        rows.push(ImportRow {
+            line: line_number,
+            name: name.to_string(),
+            email,
+            group: None,
+            exclusions: Vec::new(),
+        });
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(rows: &[ImportRow]) -> Vec<&str> {
+        rows.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn detects_the_delimiter_of_the_header() {
+        for input in [
+            "name;email\nAnna;anna@example.com\nBen;\n",
+            "name,email\nAnna,anna@example.com\nBen,\n",
+            "name\temail\nAnna\tanna@example.com\nBen\t\n",
+        ] {
+            let rows = parse(input).unwrap();
+            assert_eq!(names(&rows), ["Anna", "Ben"]);
+            assert_eq!(rows[0].email.as_deref(), Some("anna@example.com"));
+            assert_eq!(rows[1].email, None);
+        }
+    }
+
+    #[test]
+    fn reads_columns_by_their_header() {
+        let input = "\u{feff}Group;Exclusions;E-Mail;Name\n\
+                     Family;Ben, Clara;anna@example.com;Anna\n\
+                     \n\
+                     ;;;Ben\n";
+        let rows = parse(input).unwrap();
+        assert_eq!(names(&rows), ["Anna", "Ben"]);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].group.as_deref(), Some("Family"));
+        assert_eq!(rows[0].exclusions, ["Ben", "Clara"]);
+        assert_eq!(rows[1].line, 4);
+        assert_eq!(rows[1].group, None);
+        assert!(rows[1].exclusions.is_empty());
+    }
+
+    #[test]
+    fn names_with_commas_are_no_header() {
+        let rows = parse("Müller, Anna\nBen\n").unwrap();
+        assert_eq!(names(&rows), ["Müller, Anna", "Ben"]);
+    }
+
+    #[test]
+    fn reads_names_with_addresses_from_a_list() {
+        let rows = parse("  Anna Schmidt <anna@example.com>\n\nBen\n").unwrap();
+        assert_eq!(names(&rows), ["Anna Schmidt", "Ben"]);
+        assert_eq!(rows[0].email.as_deref(), Some("anna@example.com"));
+        assert_eq!(rows[0].line, 1);
+        assert_eq!(rows[1].email, None);
+        assert_eq!(rows[1].line, 3);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_entry() {
+        let error = parse("Anna\n\nBen <no address>\n").unwrap_err();
+        assert_eq!(error.line, Some(3));
+        assert_eq!(error.name, "Ben");
+        assert_eq!(error.message, "Invalid email address");
+
+        let error = parse("Anna\n<ben@example.com>\n").unwrap_err();
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.message, "Name cannot be empty");
+
+        let error = parse("name;email\nAnna;\n;ben@example.com\n").unwrap_err();
+        assert_eq!(error.line, Some(3));
+        assert_eq!(error.message, "Name cannot be empty");
+    }
+
+    #[test]
+    fn an_empty_list_is_rejected() {
+        for input in ["", "\n  \n", "name;email\n;\n"] {
+            let error = parse(input).unwrap_err();
+            assert_eq!(error.line, None);
+            assert_eq!(error.message, "There is nobody on the list");
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::{MailConfig, SmtpTls};
+use crate::models::{EventStatus, Participant, WichtelEvent};
+
+#[derive(Clone)]
+pub struct Mailer {
+    // None if mail is disabled
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    from: Mailbox,
+    public_url: String,
+}
+
+// Empty input means "no address".
+pub fn parse_email(input: &str) -> Result<Option<String>, &'static str> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    input
+        .parse::<Address>()
+        .map(|address| Some(address.to_string()))
+        .map_err(|_| "Invalid email address")
+}
+
+impl Mailer {
+    pub fn from_config(config: &MailConfig, public_url: &str) -> Result<Self, String> {
+        let from = config
+            .from
+            .parse()
+            .map_err(|e| format!("Invalid MAIL_FROM '{}': {}", config.from, e))?;
+
+        let transport = match &config.smtp {
+            None => {
+                log::info!("SMTP_HOST is not set, mail notifications are disabled");
+                None
+            }
+            Some(smtp) => {
+                let mut builder = match smtp.tls {
+                    SmtpTls::None => {
+                        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host)
+                    }
+                    SmtpTls::StartTls => {
+                        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)
+                            .map_err(|e| format!("Invalid SMTP_HOST '{}': {}", smtp.host, e))?
+                    }
+                    SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)
+                        .map_err(|e| format!("Invalid SMTP_HOST '{}': {}", smtp.host, e))?,
+                };
+                if let Some(port) = smtp.port {
+                    builder = builder.port(port);
+                }
+                if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+                    builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+                }
+                Some(builder.build())
+            }
+        };
+
+        Ok(Self {
+            transport,
+            from,
+            public_url: public_url.to_string(),
+        })
+    }
+
+    fn personal_link(&self, event: &WichtelEvent, participant: &Participant) -> String {
+        format!("{}{}", self.public_url, event.recovery_path(participant))
+    }
+
+    // Queues a mail to `participant` if they left an address. Sending happens
+    // in the background, failures are only logged.
+    fn send(&self, participant: &Participant, subject: String, body: String) {
+        let (Some(transport), Some(email)) = (&self.transport, &participant.email) else {
+            return;
+        };
+
+        let message = email
+            .parse::<Mailbox>()
+            .map_err(|e| e.to_string())
+            .and_then(|to| {
+                Message::builder()
+                    .from(self.from.clone())
+                    .to(to)
+                    .subject(subject)
+                    .header(ContentType::TEXT_PLAIN)
+                    .body(body)
+                    .map_err(|e| e.to_string())
+            });
+        let message = match message {
+            Ok(m) => m,
+            Err(e) => {
+                log::warn!("Failed to build mail for participant {}: {}", participant.id, e);
+                return;
+            }
+        };
+
+        let transport = transport.clone();
+        let participant_id = participant.id;
+        actix_web::rt::spawn(async move {
+            if let Err(e) = transport.send(message).await {
+                log::error!("Failed to send mail to participant {}: {}", participant_id, e);
+            }
+        });
+    }
+
+    pub fn send_join_confirmation(&self, event: &WichtelEvent, participant: &Participant) {
+        let drawn = if event.status == EventStatus::Closed {
+            "Names have already been drawn, so you can see whom you're buying a gift for right away."
+        } else {
+            "We'll let you know as soon as names are drawn."
+        };
+        self.send(
+            participant,
+            format!("You joined \"{}\"", event.name),
+            format!(
+                "Hi {},\n\n\
+                 you joined the Secret Santa event \"{}\". {}\n\n\
+                 Your personal link:\n{}\n\n\
+                 Keep it to yourself, it logs you in as {}.\n",
+                participant.name,
+                event.name,
+                drawn,
+                self.personal_link(event, participant),
+                participant.name
+            ),
+        );
+    }
+
+    pub fn send_draw_notifications(&self, event: &WichtelEvent) {
+        for participant in event.participants.values() {
+            self.send(
+                participant,
+                format!("Names have been drawn for \"{}\"", event.name),
+                format!(
+                    "Hi {},\n\n\
+                     names have been drawn for \"{}\"! Find out whom you're buying a gift for:\n{}\n",
+                    participant.name,
+                    event.name,
+                    self.personal_link(event, participant)
+                ),
+            );
+        }
+    }
+
+    /// Tells everyone whose recipient differs between `before` and `after`.
+    pub fn notify_changed_recipients(&self, before: &WichtelEvent, after: &WichtelEvent) {
+        for participant in after.participants.values() {
+            // Newcomers get their join confirmation instead
+            let Some(previous) = before.participants.get(&participant.id) else {
+                continue;
+            };
+            if participant.assigned_to.is_empty() || participant.assigned_to == previous.assigned_to {
+                continue;
+            }
+            self.send(
+                participant,
+                format!("Your Secret Santa recipient for \"{}\" changed", after.name),
+                format!(
+                    "Hi {},\n\n\
+                     the organizer of \"{}\" had to change whom you're buying a gift for. \
+                     See your new recipient here:\n{}\n",
+                    participant.name,
+                    after.name,
+                    self.personal_link(after, participant)
+                ),
+            );
+        }
+    }
+
+    pub fn send_reminders(&self, event: &WichtelEvent) {
+        let date = event
+            .exchange_date
+            .map(|d| format!(" on {}", d.format("%d.%m.%Y")))
+            .unwrap_or_default();
+        for participant in event.participants.values() {
+            let text = if event.status == EventStatus::Closed {
+                format!(
+                    "don't forget your gift for \"{}\"{}! Your recipient and their wishlist:",
+                    event.name, date
+                )
+            } else {
+                format!(
+                    "names for \"{}\" will be drawn soon. Take a moment to fill in your wishlist:",
+                    event.name
+                )
+            };
+            self.send(
+                participant,
+                format!("Reminder: {}", event.name),
+                format!(
+                    "Hi {},\n\n{}\n{}\n",
+                    participant.name,
+                    text,
+                    self.personal_link(event, participant)
+                ),
+            );
+        }
+    }
+}
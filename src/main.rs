@@ -1,17 +1,52 @@
+mod api;
+mod budget;
+mod config;
+mod csrf;
+mod draw;
+mod error;
+mod export;
 mod handlers;
+mod i18n;
+mod import;
+mod mailer;
 mod models;
+mod qr;
+mod rate_limit;
+mod scheduler;
+mod session_keys;
 mod state;
+mod store;
+mod verification;
 
-use actix_session::{SessionMiddleware, storage::CookieSessionStore};
-use actix_web::{cookie::Key, middleware::Logger, web, App, HttpServer};
+use actix_web::{middleware::{from_fn, Logger}, web, App, HttpServer};
+use config::{Config, StorageConfig};
+use i18n::Templates;
+use mailer::Mailer;
+use qr::QrCodes;
+use rate_limit::RateLimiter;
+use session_keys::SessionKeys;
 use state::AppState;
+use std::path::Path;
 use std::sync::Arc;
+use store::{EventStore, MemoryStore, SqliteStore};
 use tera::Tera;
 
+// Same as for JSON bodies. Imported events and participant lists easily
+// outgrow the 16 KB default of forms.
+const MAX_FORM_SIZE: usize = 2 * 1024 * 1024;
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
 
+    let config = match Config::from_env() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     let tera = match Tera::new("templates/**/*.html") {
         Ok(t) => t,
         Err(e) => {
@@ -20,34 +55,128 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
-    let app_state = Arc::new(AppState::new());
-    let secret_key = Key::generate();
+    let templates = match Templates::load(tera, Path::new("locales")) {
+        Ok(t) => web::Data::new(t),
+        Err(e) => {
+            eprintln!("Translation error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let store: Box<dyn EventStore> = match &config.storage {
+        StorageConfig::Memory => {
+            log::warn!("Using in-memory storage, events will be lost on restart");
+            Box::new(MemoryStore::new())
+        }
+        StorageConfig::Sqlite { path } => match SqliteStore::open(path) {
+            Ok(s) => Box::new(s),
+            Err(e) => {
+                eprintln!("Failed to open database {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let session_keys = match SessionKeys::load(&config.session, config.environment) {
+        Ok(k) => k,
+        Err(e) => {
+            eprintln!("Session key error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mailer = match Mailer::from_config(&config.mail, &config.public_url) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Mail configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let rate_limiter = match RateLimiter::new(&config.limits) {
+        Ok(l) => web::Data::new(l),
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let qr_codes = web::Data::new(QrCodes::new(&config.public_url));
+
+    let app_state = Arc::new(AppState::new(store, config.limits.participants_per_event));
+    actix_web::rt::spawn(scheduler::run(app_state.clone(), mailer.clone()));
+
+    let bind_addr = config.bind_address.clone();
 
-    let bind_addr = std::env::var("BIND_ADDRESS").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
-    
     println!("🎄 Wichtel Loser starting at http://{}", bind_addr);
     
     HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(csrf::verify))
             .wrap(Logger::default())
-            .wrap(SessionMiddleware::new(
-                CookieSessionStore::default(),
-                secret_key.clone(),
-            ))
-            .app_data(web::Data::new(tera.clone()))
+            .wrap(session_keys.session_middleware())
+            .wrap(from_fn(session_keys::rotate_session_cookie))
+            .wrap(from_fn(rate_limit::limit))
+            .wrap(from_fn(error::negotiate))
+            .app_data(web::Data::new(session_keys.clone()))
+            .app_data(templates.clone())
+            .app_data(web::Data::new(mailer.clone()))
+            .app_data(rate_limiter.clone())
+            .app_data(qr_codes.clone())
             .app_data(web::Data::from(app_state.clone()))
+            .app_data(web::FormConfig::default().limit(MAX_FORM_SIZE))
+            .app_data(web::PayloadConfig::new(MAX_FORM_SIZE))
             .service(handlers::index)
+            .service(handlers::choose_language)
             .service(handlers::create_event_page)
             .service(handlers::create_event)
+            .service(handlers::import_event_page)
+            .service(handlers::import_event)
             .service(handlers::join_page)
             .service(handlers::join_event)
             .service(handlers::manage_event)
+            .service(handlers::qr_code_sheet)
+            .service(handlers::invite_qr_code)
+            .service(handlers::participant_qr_code)
+            .service(handlers::fold_cards)
+            .service(handlers::export_event)
+            .service(handlers::export_participants)
+            .service(handlers::export_assignments)
             .service(handlers::close_event)
+            .service(handlers::send_reminders)
+            .service(handlers::set_exchange_date)
+            .service(handlers::set_budget)
+            .service(handlers::set_registration_deadline)
+            .service(handlers::set_draw_mode)
+            .service(handlers::set_gifts_per_person)
+            .service(handlers::clone_event)
+            .service(handlers::set_history)
+            .service(handlers::set_late_join)
+            .service(handlers::set_event_language)
+            .service(handlers::publish_commitment)
+            .service(handlers::withdraw_commitment)
+            .service(handlers::reveal_seed)
+            .service(handlers::rename_participant)
+            .service(handlers::remove_participant)
+            .service(handlers::merge_participants)
+            .service(handlers::import_participants)
+            .service(handlers::remove_and_repair)
+            .service(handlers::remove_and_redraw)
+            .service(handlers::add_pair_exclusion)
+            .service(handlers::add_group_exclusion)
+            .service(handlers::remove_exclusion)
             .service(handlers::view_assignment)
+            .service(handlers::verify_draw)
+            .service(handlers::update_wishlist)
+            .service(handlers::update_email)
+            .service(handlers::send_message)
             .service(handlers::identify_page)
             .service(handlers::search_participants)
             .service(handlers::confirm_identity)
+            .service(handlers::recover_identity)
+            .service(web::scope("/api/v1").configure(api::configure))
             .service(actix_files::Files::new("/static", "static").show_files_listing())
+            .default_service(web::to(handlers::not_found))
     })
     .bind(&bind_addr)?
     .run()
@@ -1,14 +1,134 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use crate::budget::Budget;
+use crate::draw::{DrawError, DrawMode, HistoryConstraint};
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::import::{ImportError, ImportRow};
+use crate::verification::{self, DrawCommitment, DrawInput, DrawRecord, WithdrawnCommitment};
+
+const MAX_WISHLIST_ITEMS: usize = 30;
+const MAX_WISHLIST_TEXT: usize = 500;
+const MAX_MESSAGE_LENGTH: usize = 1000;
+const MAX_MESSAGES_PER_THREAD: usize = 200;
+pub const MAX_GIFTS_PER_PERSON: usize = 5;
+const MAX_SPLICE_ATTEMPTS: usize = 20;
+// Earlier draws kept per event. Nobody remembers further back than that.
+const MAX_PAST_DRAWS: usize = 10;
+const DEFAULT_HISTORY_YEARS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WishlistItem {
+    pub text: String,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wishlist {
+    pub items: Vec<WishlistItem>,
+    pub sizes: String,
+    pub dislikes: String,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Wishlist {
+    /// Parses one item per line. An http(s) URL anywhere in the line becomes
+    /// the item's link, the rest of the line its description.
+    pub fn parse_items(input: &str) -> Vec<WishlistItem> {
+        input
+            .lines()
+            .filter_map(|line| {
+                let mut link = None;
+                let mut words = Vec::new();
+                for word in line.split_whitespace() {
+                    if link.is_none()
+                        && (word.starts_with("https://") || word.starts_with("http://"))
+                    {
+                        link = Some(word.to_string());
+                    } else {
+                        words.push(word);
+                    }
+                }
+                let text = words.join(" ");
+                if text.is_empty() && link.is_none() {
+                    return None;
+                }
+                Some(WishlistItem { text, link })
+            })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty() && self.sizes.is_empty() && self.dislikes.is_empty()
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Participant {
     pub id: Uuid,
     pub name: String,
     pub joined_at: DateTime<Utc>,
-    pub assigned_to: Option<Uuid>,
+    /// Recipients in the order they were drawn, one per gift.
+    #[serde(default, deserialize_with = "deserialize_assigned_to")]
+    pub assigned_to: Vec<Uuid>,
+    /// Needed to reclaim this identity from another browser.
+    #[serde(default = "generate_pin")]
+    pub pin: String,
+    /// Secret part of the personal link that logs the participant back in.
+    #[serde(default = "Uuid::new_v4")]
+    pub recovery_token: Uuid,
+    #[serde(default)]
+    pub wishlist: Wishlist,
+    /// Optional address for notifications, only ever shown to its owner.
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Set when the organizer changed this participant's recipient after the
+    /// draw, so they can be told about it.
+    #[serde(default)]
+    pub recipient_changed_at: Option<DateTime<Utc>>,
+}
+
+impl Participant {
+    pub fn new(name: String, email: Option<String>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            joined_at: Utc::now(),
+            assigned_to: Vec::new(),
+            pin: generate_pin(),
+            recovery_token: Uuid::new_v4(),
+            wishlist: Wishlist::default(),
+            email,
+            recipient_changed_at: None,
+        }
+    }
+}
+
+// Names that only differ in case or whitespace are taken to be the same.
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// Events stored before multi-gift draws have a single optional id here.
+fn deserialize_assigned_to<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Uuid>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Single(Option<Uuid>),
+        List(Vec<Uuid>),
+    }
+
+    Ok(match Stored::deserialize(deserializer)? {
+        Stored::Single(id) => id.into_iter().collect(),
+        Stored::List(ids) => ids,
+    })
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -17,6 +137,90 @@ pub enum EventStatus {
     Closed,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Exclusion {
+    /// `giver` must not draw `receiver`, and vice versa if `mutual` is set.
+    Pair {
+        id: Uuid,
+        giver: Uuid,
+        receiver: Uuid,
+        mutual: bool,
+    },
+    /// Nobody in the group may draw another member, e.g. a household.
+    Group {
+        id: Uuid,
+        label: String,
+        members: Vec<Uuid>,
+    },
+}
+
+impl Exclusion {
+    pub fn id(&self) -> Uuid {
+        match self {
+            Exclusion::Pair { id, .. } | Exclusion::Group { id, .. } => *id,
+        }
+    }
+
+    pub fn forbids(&self, giver: Uuid, receiver: Uuid) -> bool {
+        match self {
+            Exclusion::Pair {
+                giver: g,
+                receiver: r,
+                mutual,
+                ..
+            } => (*g == giver && *r == receiver) || (*mutual && *g == receiver && *r == giver),
+            Exclusion::Group { members, .. } => {
+                members.contains(&giver) && members.contains(&receiver)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageAuthor {
+    Giver,
+    Receiver,
+}
+
+/// A message in the anonymous thread between a giver and their recipient.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub id: Uuid,
+    pub giver: Uuid,
+    pub receiver: Uuid,
+    pub author: MessageAuthor,
+    pub body: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+/// Which of their two threads a participant is writing in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageThread {
+    /// With the person they are buying a gift for.
+    Recipient,
+    /// With the anonymous person buying a gift for them.
+    Wichtel,
+}
+
+/// The pairs of an earlier event in the same series.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PastDraw {
+    pub event_id: Uuid,
+    pub name: String,
+    pub drawn_at: DateTime<Utc>,
+    pub pairs: Vec<(Uuid, Uuid)>,
+}
+
+/// Outcome of the automatic close at the registration deadline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoCloseResult {
+    pub at: DateTime<Utc>,
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WichtelEvent {
     pub id: Uuid,
@@ -25,6 +229,53 @@ pub struct WichtelEvent {
     pub invite_code: String,
     pub status: EventStatus,
     pub participants: HashMap<Uuid, Participant>,
+    #[serde(default)]
+    pub exclusions: Vec<Exclusion>,
+    #[serde(default)]
+    pub draw_mode: DrawMode,
+    /// How many gifts everyone gives and receives.
+    #[serde(default = "default_gifts_per_person")]
+    pub gifts_per_person: usize,
+    /// Day the gifts are handed over; wishlists are frozen after it.
+    #[serde(default)]
+    pub exchange_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub messages: Vec<Message>,
+    /// Ids of participants merged away by the organizer, pointing to the entry
+    /// they were merged into, so their browser sessions keep working.
+    #[serde(default)]
+    pub merged_participants: HashMap<Uuid, Uuid>,
+    /// Whether people may still join after the draw.
+    #[serde(default)]
+    pub late_join: bool,
+    /// Names are drawn automatically once this has passed.
+    #[serde(default)]
+    pub registration_deadline: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub auto_close_result: Option<AutoCloseResult>,
+    #[serde(default)]
+    pub budget: Option<Budget>,
+    /// Published before the draw; participants and exclusions are locked
+    /// while it is pending.
+    #[serde(default)]
+    pub draw_commitment: Option<DrawCommitment>,
+    #[serde(default)]
+    pub withdrawn_commitments: Vec<WithdrawnCommitment>,
+    #[serde(default)]
+    pub draw_record: Option<DrawRecord>,
+    /// Draws of the events this one was cloned from, most recent first.
+    /// Participants keep their ids from year to year.
+    #[serde(default)]
+    pub pair_history: Vec<PastDraw>,
+    /// How many of the most recent past draws to avoid repeating pairs from.
+    #[serde(default)]
+    pub history_years: usize,
+    #[serde(default)]
+    pub history_constraint: HistoryConstraint,
+    /// Language of the event's pages unless a visitor picks another one.
+    /// `None` follows each browser's language.
+    #[serde(default)]
+    pub language: Option<Locale>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -38,51 +289,1020 @@ impl WichtelEvent {
             invite_code,
             status: EventStatus::Open,
             participants: HashMap::new(),
+            exclusions: Vec::new(),
+            draw_mode: DrawMode::default(),
+            gifts_per_person: 1,
+            exchange_date: None,
+            messages: Vec::new(),
+            merged_participants: HashMap::new(),
+            late_join: false,
+            registration_deadline: None,
+            auto_close_result: None,
+            budget: None,
+            draw_commitment: None,
+            withdrawn_commitments: Vec::new(),
+            draw_record: None,
+            pair_history: Vec::new(),
+            history_years: 0,
+            history_constraint: HistoryConstraint::default(),
+            language: None,
             created_at: Utc::now(),
         }
     }
 
-    pub fn add_participant(&mut self, name: String) -> Uuid {
-        let participant = Participant {
-            id: Uuid::new_v4(),
-            name,
-            joined_at: Utc::now(),
-            assigned_to: None,
-        };
+    pub fn add_participant(&mut self, name: String, email: Option<String>) -> Uuid {
+        let participant = Participant::new(name, email);
         let id = participant.id;
         self.participants.insert(id, participant);
         id
     }
 
-    pub fn close_and_assign(&mut self) -> Result<(), &'static str> {
+    pub fn accepts_participants(&self) -> bool {
+        match self.status {
+            EventStatus::Open => !self.registration_ended() && self.draw_commitment.is_none(),
+            EventStatus::Closed => self.late_join,
+        }
+    }
+
+    fn registration_ended(&self) -> bool {
+        self.registration_deadline
+            .is_some_and(|deadline| deadline <= Utc::now())
+    }
+
+    pub fn set_registration_deadline(
+        &mut self,
+        deadline: Option<DateTime<Utc>>,
+    ) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("The registration deadline cannot be changed after the draw");
+        }
+        if deadline.is_some_and(|deadline| deadline <= Utc::now()) {
+            return Err("The registration deadline must be in the future");
+        }
+        self.registration_deadline = deadline;
+        self.auto_close_result = None;
+        Ok(())
+    }
+
+    /// When the scheduler should close this event, if at all. Every deadline
+    /// is only tried once; setting a new one allows another attempt.
+    pub fn pending_auto_close(&self) -> Option<DateTime<Utc>> {
+        if self.status != EventStatus::Open || self.auto_close_result.is_some() {
+            return None;
+        }
+        self.registration_deadline
+    }
+
+    /// Draws names if the registration deadline has passed and records the
+    /// outcome. Returns `None` if nothing was due.
+    pub fn auto_close(&mut self, now: DateTime<Utc>) -> Option<Result<(), AppError>> {
+        if self.pending_auto_close().is_none_or(|deadline| deadline > now) {
+            return None;
+        }
+        let result = self.close_and_assign();
+        self.auto_close_result = Some(AutoCloseResult {
+            at: now,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        Some(result)
+    }
+
+    /// Adds a participant, splicing them into the existing draw if names have
+    /// already been drawn: for every gift, a random giver now draws the
+    /// newcomer, who takes over that giver's previous recipient. No other
+    /// assignment changes.
+    pub fn join(&mut self, name: String, email: Option<String>) -> Result<Uuid, &'static str> {
+        if self.status == EventStatus::Open {
+            if self.registration_ended() {
+                return Err("Registration for this event has ended");
+            }
+            self.check_not_committed()?;
+            return Ok(self.add_participant(name, email));
+        }
+        if !self.late_join {
+            return Err("This event is already closed for new participants");
+        }
+
+        let pairs: Vec<(Uuid, Uuid)> = self
+            .participants
+            .values()
+            .flat_map(|p| p.assigned_to.iter().map(move |&r| (p.id, r)))
+            .collect();
+        if pairs.is_empty() {
+            return Err("There is no draw to join");
+        }
+        let spliced = self
+            .pick_splice_pairs(&pairs)
+            .ok_or("There is no room for another participant in this draw")?;
+
+        let id = self.add_participant(name, email);
+        let now = Utc::now();
+        for (giver, recipient) in spliced {
+            if let Some(newcomer) = self.participants.get_mut(&id) {
+                newcomer.assigned_to.push(recipient);
+            }
+            if let Some(giver) = self.participants.get_mut(&giver) {
+                for assigned in giver.assigned_to.iter_mut().filter(|r| **r == recipient) {
+                    *assigned = id;
+                }
+                giver.recipient_changed_at = Some(now);
+            }
+        }
+        self.mark_draw_amended();
+        Ok(id)
+    }
+
+    // Randomly picks one existing pair per gift to splice a newcomer into.
+    // The givers must differ, or one of them would draw the newcomer twice,
+    // and so must the recipients, or the newcomer would draw someone twice.
+    fn pick_splice_pairs(&self, pairs: &[(Uuid, Uuid)]) -> Option<Vec<(Uuid, Uuid)>> {
+        let mut rng = rand::thread_rng();
+        for _ in 0..MAX_SPLICE_ATTEMPTS {
+            let mut shuffled = pairs.to_vec();
+            shuffled.shuffle(&mut rng);
+            let mut picked: Vec<(Uuid, Uuid)> = Vec::new();
+            for (giver, recipient) in shuffled {
+                if picked.iter().all(|&(g, r)| g != giver && r != recipient) {
+                    picked.push((giver, recipient));
+                }
+                if picked.len() == self.gifts_per_person {
+                    return Some(picked);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn set_email(&mut self, participant_id: Uuid, email: Option<String>) -> Result<(), &'static str> {
+        let participant = self
+            .participants
+            .get_mut(&participant_id)
+            .ok_or("Participant not found in this event")?;
+        participant.email = email;
+        Ok(())
+    }
+
+    pub fn rename_participant(&mut self, id: Uuid, name: &str) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("Participants cannot be changed after the draw");
+        }
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Name cannot be empty");
+        }
+        let participant = self
+            .participants
+            .get_mut(&id)
+            .ok_or("Participant not found in this event")?;
+        participant.name = name.to_string();
+        Ok(())
+    }
+
+    pub fn remove_participant(&mut self, id: Uuid) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("Participants cannot be changed after the draw");
+        }
+        self.check_not_committed()?;
+        self.drop_participant(id)?;
+        Ok(())
+    }
+
+    // Removes a participant together with everything that refers to them.
+    fn drop_participant(&mut self, id: Uuid) -> Result<Participant, &'static str> {
+        let participant = self
+            .participants
+            .remove(&id)
+            .ok_or("Participant not found in this event")?;
+        self.replace_in_exclusions(id, None);
+        self.merged_participants.retain(|_, target| *target != id);
+        self.messages.retain(|m| m.giver != id && m.receiver != id);
+        Ok(participant)
+    }
+
+    /// Removes `leaver` after the draw and lets their givers take over the
+    /// leaver's recipients. Nobody else's assignment changes.
+    pub fn remove_and_repair(&mut self, leaver: Uuid) -> Result<(), AppError> {
+        if self.status != EventStatus::Closed {
+            return Err(AppError::Conflict("Names have not been drawn yet".to_string()));
+        }
+        let recipients = self
+            .participants
+            .get(&leaver)
+            .ok_or(AppError::NotFound("Participant not found in this event"))?
+            .assigned_to
+            .clone();
+        let givers = self.givers_of(leaver);
+
+        if let ([giver], [recipient]) = (givers.as_slice(), recipients.as_slice()) {
+            if giver == recipient {
+                return Err(AppError::Conflict(format!(
+                    "{} and {} drew each other, so {} would be left to draw themselves. \
+                     Re-draw all names instead.",
+                    self.participants[giver].name,
+                    self.participants[&leaver].name,
+                    self.participants[giver].name
+                )));
+            }
+            if self.is_excluded(*giver, *recipient) {
+                return Err(AppError::Conflict(format!(
+                    "{} would have to take over {}, which an exclusion forbids. \
+                     Re-draw all names instead.",
+                    self.participants[giver].name, self.participants[recipient].name
+                )));
+            }
+        }
+        let handovers = self.match_handovers(leaver, &givers, &recipients).ok_or_else(|| {
+            AppError::Conflict(format!(
+                "{} cannot take over the recipients of {} without someone drawing \
+                 themselves, the same person twice or someone they are excluded from. \
+                 Re-draw all names instead.",
+                self.participant_names(&givers),
+                self.participants[&leaver].name
+            ))
+        })?;
+
+        self.drop_participant(leaver).map_err(AppError::NotFound)?;
+        let now = Utc::now();
+        for (giver, recipient) in handovers {
+            if let Some(giver) = self.participants.get_mut(&giver) {
+                for assigned in giver.assigned_to.iter_mut().filter(|r| **r == leaver) {
+                    *assigned = recipient;
+                }
+                giver.recipient_changed_at = Some(now);
+            }
+        }
+        self.mark_draw_amended();
+        Ok(())
+    }
+
+    // Pairs every giver of `leaver` with one of the leaver's recipients such
+    // that nobody draws themselves, someone they already draw, or someone
+    // they are excluded from. Tries the recipients in random order.
+    fn match_handovers(
+        &self,
+        leaver: Uuid,
+        givers: &[Uuid],
+        recipients: &[Uuid],
+    ) -> Option<Vec<(Uuid, Uuid)>> {
+        fn assign(
+            event: &WichtelEvent,
+            leaver: Uuid,
+            givers: &[Uuid],
+            remaining: &mut Vec<Uuid>,
+            matched: &mut Vec<(Uuid, Uuid)>,
+        ) -> bool {
+            let Some((&giver, rest)) = givers.split_first() else {
+                return true;
+            };
+            let already = &event.participants[&giver].assigned_to;
+            for i in 0..remaining.len() {
+                let recipient = remaining[i];
+                if recipient == giver
+                    || (recipient != leaver && already.contains(&recipient))
+                    || event.forbids_pair(giver, recipient)
+                {
+                    continue;
+                }
+                remaining.swap_remove(i);
+                matched.push((giver, recipient));
+                if assign(event, leaver, rest, remaining, matched) {
+                    return true;
+                }
+                matched.pop();
+                remaining.push(recipient);
+                let last = remaining.len() - 1;
+                remaining.swap(i, last);
+            }
+            false
+        }
+
+        if givers.len() != recipients.len() {
+            return None;
+        }
+        let mut remaining = recipients.to_vec();
+        remaining.shuffle(&mut rand::thread_rng());
+        let mut matched = Vec::with_capacity(givers.len());
+        assign(self, leaver, givers, &mut remaining, &mut matched).then_some(matched)
+    }
+
+    /// Removes `leaver` after the draw and draws all names again. The event is
+    /// left untouched if the new draw fails.
+    pub fn remove_and_redraw(&mut self, leaver: Uuid) -> Result<(), AppError> {
+        if self.status != EventStatus::Closed {
+            return Err(AppError::Conflict("Names have not been drawn yet".to_string()));
+        }
+
+        let mut redrawn = self.clone();
+        redrawn.drop_participant(leaver).map_err(AppError::NotFound)?;
+        redrawn.status = EventStatus::Open;
+        for participant in redrawn.participants.values_mut() {
+            participant.assigned_to.clear();
+        }
+        redrawn.close_and_assign()?;
+
+        let now = Utc::now();
+        for participant in redrawn.participants.values_mut() {
+            let before = &self.participants[&participant.id].assigned_to;
+            if &participant.assigned_to != before {
+                participant.recipient_changed_at = Some(now);
+            }
+        }
+        if let Some(record) = &self.draw_record {
+            redrawn.withdrawn_commitments.push(WithdrawnCommitment {
+                commitment: record.commitment.clone(),
+                published_at: record.committed_at.unwrap_or(record.drawn_at),
+                withdrawn_at: now,
+            });
+        }
+        *self = redrawn;
+        Ok(())
+    }
+
+    /// Folds `duplicate` into `keep`. `keep` inherits the duplicate's wishlist
+    /// if it has none and takes its place in all exclusions.
+    pub fn merge_participants(&mut self, keep: Uuid, duplicate: Uuid) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("Participants cannot be changed after the draw");
+        }
+        self.check_not_committed()?;
+        if keep == duplicate {
+            return Err("Pick two different participants");
+        }
+        if !self.participants.contains_key(&keep) {
+            return Err("Participant not found in this event");
+        }
+        let duplicate_participant = self
+            .participants
+            .remove(&duplicate)
+            .ok_or("Participant not found in this event")?;
+
+        if let Some(kept) = self.participants.get_mut(&keep) {
+            if kept.wishlist.is_empty() {
+                kept.wishlist = duplicate_participant.wishlist;
+            }
+        }
+        self.replace_in_exclusions(duplicate, Some(keep));
+        for target in self.merged_participants.values_mut() {
+            if *target == duplicate {
+                *target = keep;
+            }
+        }
+        self.merged_participants.insert(duplicate, keep);
+        Ok(())
+    }
+
+    // Swaps `old` for `new` in every exclusion, or drops it if `new` is None.
+    // Exclusions that no longer make sense afterwards are removed.
+    fn replace_in_exclusions(&mut self, old: Uuid, new: Option<Uuid>) {
+        self.exclusions.retain_mut(|exclusion| match exclusion {
+            Exclusion::Pair {
+                giver, receiver, ..
+            } => {
+                for id in [&mut *giver, &mut *receiver] {
+                    if *id == old {
+                        match new {
+                            Some(new) => *id = new,
+                            None => return false,
+                        }
+                    }
+                }
+                giver != receiver
+            }
+            Exclusion::Group { members, .. } => {
+                let mut seen = HashSet::new();
+                members.retain_mut(|member| {
+                    if *member == old {
+                        match new {
+                            Some(new) => *member = new,
+                            None => return false,
+                        }
+                    }
+                    seen.insert(*member)
+                });
+                members.len() >= 2
+            }
+        });
+    }
+
+    /// Participants whose names only differ in case or whitespace from
+    /// someone else's, which usually means somebody joined twice.
+    pub fn likely_duplicates(&self) -> Vec<Uuid> {
+        let mut by_name: HashMap<String, Vec<Uuid>> = HashMap::new();
+        for participant in self.participants.values() {
+            by_name
+                .entry(normalize_name(&participant.name))
+                .or_default()
+                .push(participant.id);
+        }
+        by_name
+            .into_values()
+            .filter(|ids| ids.len() > 1)
+            .flatten()
+            .collect()
+    }
+
+    pub fn add_exclusion(&mut self, exclusion: Exclusion) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("Exclusions cannot be changed after the draw");
+        }
+        self.check_not_committed()?;
+
+        let members: Vec<Uuid> = match &exclusion {
+            Exclusion::Pair {
+                giver, receiver, ..
+            } => {
+                if giver == receiver {
+                    return Err("Pick two different participants");
+                }
+                vec![*giver, *receiver]
+            }
+            Exclusion::Group { members, .. } => {
+                if members.len() < 2 {
+                    return Err("A group needs at least 2 members");
+                }
+                members.clone()
+            }
+        };
+        if members.iter().any(|id| !self.participants.contains_key(id)) {
+            return Err("Participant not found in this event");
+        }
+
+        self.exclusions.push(exclusion);
+        Ok(())
+    }
+
+    /// Adds everyone on an imported list along with their exclusions, or
+    /// nobody if any line is invalid. Members of a group are kept from drawing
+    /// each other; a group with the label of an existing one is added to it.
+    pub fn import_participants(&mut self, rows: Vec<ImportRow>) -> Result<Vec<Uuid>, ImportError> {
+        if self.status == EventStatus::Closed {
+            return Err("Participants cannot be changed after the draw".into());
+        }
+        self.check_not_committed()?;
+
+        let mut ids: HashMap<String, Uuid> = self
+            .participants
+            .values()
+            .map(|p| (normalize_name(&p.name), p.id))
+            .collect();
+        let mut imported = Vec::new();
+        for row in &rows {
+            let key = normalize_name(&row.name);
+            if imported.iter().any(|(k, _)| *k == key) {
+                return Err(ImportError::at(row.line, "{name} is on the list twice", &row.name));
+            }
+            if ids.contains_key(&key) {
+                return Err(ImportError::at(row.line, "{name} is already taking part", &row.name));
+            }
+            imported.push((key, Participant::new(row.name.clone(), row.email.clone())));
+        }
+        ids.extend(imported.iter().map(|(key, p)| (key.clone(), p.id)));
+
+        let mut pairs: Vec<(Uuid, Uuid)> = Vec::new();
+        let mut groups: Vec<(String, Vec<Uuid>)> = Vec::new();
+        for (row, (_, participant)) in rows.iter().zip(&imported) {
+            for excluded in &row.exclusions {
+                let &other = ids.get(&normalize_name(excluded)).ok_or_else(|| {
+                    ImportError::at(row.line, "Nobody called {name} is taking part", excluded)
+                })?;
+                if other == participant.id {
+                    return Err(ImportError::at(row.line, "{name} cannot exclude themselves", &row.name));
+                }
+                // Both sides may list each other.
+                let pair = (participant.id, other);
+                if !pairs.iter().any(|&(a, b)| (a, b) == pair || (b, a) == pair) {
+                    pairs.push(pair);
+                }
+            }
+            if let Some(label) = &row.group {
+                match groups.iter_mut().find(|(l, _)| normalize_name(l) == normalize_name(label)) {
+                    Some((_, members)) => members.push(participant.id),
+                    None => groups.push((label.clone(), vec![participant.id])),
+                }
+            }
+        }
+
+        let imported_ids = imported.iter().map(|(_, p)| p.id).collect();
+        self.participants
+            .extend(imported.into_iter().map(|(_, p)| (p.id, p)));
+        for (giver, receiver) in pairs {
+            self.exclusions.push(Exclusion::Pair {
+                id: Uuid::new_v4(),
+                giver,
+                receiver,
+                mutual: true,
+            });
+        }
+        for (label, new_members) in groups {
+            let existing = self.exclusions.iter_mut().find_map(|e| match e {
+                Exclusion::Group { label: l, members, .. }
+                    if normalize_name(l) == normalize_name(&label) =>
+                {
+                    Some(members)
+                }
+                _ => None,
+            });
+            match existing {
+                Some(members) => members.extend(new_members),
+                // A group of one keeps nobody apart.
+                None if new_members.len() >= 2 => self.exclusions.push(Exclusion::Group {
+                    id: Uuid::new_v4(),
+                    label,
+                    members: new_members,
+                }),
+                None => {}
+            }
+        }
+        Ok(imported_ids)
+    }
+
+    /// Checks that everything refers to participants of this event, for
+    /// events that come from outside, like imported exports.
+    pub fn check_consistency(&self) -> Result<(), &'static str> {
+        if !(1..=MAX_GIFTS_PER_PERSON).contains(&self.gifts_per_person) {
+            return Err("The number of gifts per person is out of range");
+        }
+        let known = |id: &Uuid| self.participants.contains_key(id);
+        let exclusions_known = self.exclusions.iter().all(|exclusion| match exclusion {
+            Exclusion::Pair {
+                giver, receiver, ..
+            } => known(giver) && known(receiver),
+            Exclusion::Group { members, .. } => members.iter().all(known),
+        });
+        let participants_known = self
+            .participants
+            .iter()
+            .all(|(id, p)| *id == p.id && p.assigned_to.iter().all(known));
+        if !exclusions_known || !participants_known {
+            return Err("The event refers to participants it doesn't have");
+        }
+        Ok(())
+    }
+
+    pub fn wishlist_editable(&self) -> bool {
+        match self.exchange_date {
+            Some(date) => Utc::now().date_naive() <= date,
+            None => true,
+        }
+    }
+
+    pub fn update_wishlist(
+        &mut self,
+        participant_id: Uuid,
+        mut wishlist: Wishlist,
+    ) -> Result<(), &'static str> {
+        if !self.wishlist_editable() {
+            return Err("Wishlists can no longer be changed after the exchange date");
+        }
+        if wishlist.items.len() > MAX_WISHLIST_ITEMS {
+            return Err("Too many wishlist items");
+        }
+        let too_long = |s: &str| s.chars().count() > MAX_WISHLIST_TEXT;
+        if too_long(&wishlist.sizes)
+            || too_long(&wishlist.dislikes)
+            || wishlist.items.iter().any(|i| too_long(&i.text))
+        {
+            return Err("Wishlist entries are too long");
+        }
+
+        let participant = self
+            .participants
+            .get_mut(&participant_id)
+            .ok_or("Participant not found in this event")?;
+        wishlist.updated_at = Some(Utc::now());
+        participant.wishlist = wishlist;
+        Ok(())
+    }
+
+    pub fn set_draw_mode(&mut self, mode: DrawMode) -> Result<(), &'static str> {
         if self.status == EventStatus::Closed {
-            return Err("Event is already closed");
+            return Err("The draw mode cannot be changed after the draw");
         }
-        if self.participants.len() < 2 {
-            return Err("Need at least 2 participants");
+        self.check_not_committed()?;
+        self.draw_mode = mode;
+        Ok(())
+    }
+
+    pub fn remove_exclusion(&mut self, exclusion_id: Uuid) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("Exclusions cannot be changed after the draw");
         }
+        self.check_not_committed()?;
+        let before = self.exclusions.len();
+        self.exclusions.retain(|e| e.id() != exclusion_id);
+        if self.exclusions.len() == before {
+            return Err("Exclusion not found");
+        }
+        Ok(())
+    }
+
+    pub fn is_excluded(&self, giver: Uuid, receiver: Uuid) -> bool {
+        self.exclusions.iter().any(|e| e.forbids(giver, receiver))
+    }
 
+    fn draw_input(&self) -> DrawInput {
         let participant_ids: Vec<Uuid> = self.participants.keys().cloned().collect();
-        let assignments = generate_assignments(&participant_ids)?;
-        
-        for (giver, receiver) in assignments {
+        DrawInput::new(
+            self.id,
+            self.draw_mode,
+            self.gifts_per_person,
+            &participant_ids,
+            |giver, receiver| self.is_excluded(giver, receiver),
+        )
+        .with_history(self.history_constraint, self.avoided_draws().map(|d| d.pairs.as_slice()))
+    }
+
+    fn avoided_draws(&self) -> impl Iterator<Item = &PastDraw> {
+        self.pair_history.iter().take(self.history_years)
+    }
+
+    // Whether the pair is ruled out for the draw, as opposed to merely
+    // undesirable under a soft history constraint.
+    fn forbids_pair(&self, giver: Uuid, receiver: Uuid) -> bool {
+        self.is_excluded(giver, receiver)
+            || (self.history_constraint == HistoryConstraint::Hard
+                && self.avoided_draws().any(|d| d.pairs.contains(&(giver, receiver))))
+    }
+
+    /// Current pairs that already occurred in one of the avoided past draws.
+    pub fn repeated_pairs(&self) -> usize {
+        self.participants
+            .values()
+            .flat_map(|p| p.assigned_to.iter().map(move |&r| (p.id, r)))
+            .filter(|pair| self.avoided_draws().any(|d| d.pairs.contains(pair)))
+            .count()
+    }
+
+    /// A fresh event for the next round of the series: same participants,
+    /// exclusions and settings, with this event's draw added to the history.
+    pub fn next_year(&self, name: &str) -> Result<WichtelEvent, &'static str> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Event name cannot be empty");
+        }
+
+        let mut next = WichtelEvent::new(name.to_string());
+        for participant in self.participants.values() {
+            // Keeping the id is what links the years together
+            let carried = Participant {
+                id: participant.id,
+                ..Participant::new(participant.name.clone(), participant.email.clone())
+            };
+            next.participants.insert(carried.id, carried);
+        }
+        next.exclusions = self.exclusions.clone();
+        next.draw_mode = self.draw_mode;
+        next.gifts_per_person = self.gifts_per_person;
+        next.budget = self.budget.clone();
+        next.late_join = self.late_join;
+        next.history_constraint = self.history_constraint;
+        next.language = self.language;
+
+        if self.status == EventStatus::Closed {
+            next.pair_history.push(PastDraw {
+                event_id: self.id,
+                name: self.name.clone(),
+                drawn_at: self
+                    .draw_record
+                    .as_ref()
+                    .map_or(self.created_at, |record| record.drawn_at),
+                pairs: self
+                    .participants
+                    .values()
+                    .flat_map(|p| p.assigned_to.iter().map(move |&r| (p.id, r)))
+                    .collect(),
+            });
+        }
+        next.pair_history.extend(self.pair_history.iter().cloned());
+        next.pair_history.truncate(MAX_PAST_DRAWS);
+        next.history_years = match self.history_years {
+            0 => DEFAULT_HISTORY_YEARS,
+            years => years,
+        }
+        .min(next.pair_history.len());
+        Ok(next)
+    }
+
+    pub fn set_history(
+        &mut self,
+        years: usize,
+        constraint: HistoryConstraint,
+    ) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("Earlier years cannot be changed after the draw");
+        }
+        self.check_not_committed()?;
+        if years > self.pair_history.len() {
+            return Err("There are not that many earlier years");
+        }
+        self.history_years = years;
+        self.history_constraint = constraint;
+        Ok(())
+    }
+
+    fn check_not_committed(&self) -> Result<(), &'static str> {
+        if self.draw_commitment.is_some() {
+            return Err("The draw is committed to the current participants and exclusions. \
+                Withdraw the commitment first.");
+        }
+        Ok(())
+    }
+
+    /// Fixes the seed of the upcoming draw and publishes its hash together
+    /// with the participants and exclusions, so nobody can change the
+    /// outcome unnoticed afterwards.
+    pub fn publish_commitment(&mut self) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("Names have already been drawn");
+        }
+        if self.draw_commitment.is_some() {
+            return Err("A commitment has already been published");
+        }
+        if self.participants.len() < self.min_participants() {
+            return Err("Not enough participants to draw names yet");
+        }
+        self.draw_commitment = Some(DrawCommitment::new(
+            verification::generate_seed(),
+            &self.draw_input(),
+        ));
+        Ok(())
+    }
+
+    pub fn withdraw_commitment(&mut self) -> Result<(), &'static str> {
+        let withdrawn = self
+            .draw_commitment
+            .take()
+            .ok_or("No commitment has been published")?;
+        self.withdrawn_commitments.push(WithdrawnCommitment {
+            commitment: withdrawn.commitment,
+            published_at: withdrawn.published_at,
+            withdrawn_at: Utc::now(),
+        });
+        Ok(())
+    }
+
+    /// Publishes the seed of the draw. Anyone can then recompute all pairs.
+    pub fn reveal_seed(&mut self) -> Result<(), &'static str> {
+        let record = self
+            .draw_record
+            .as_mut()
+            .ok_or("Names have not been drawn yet")?;
+        if record.seed_revealed_at.is_some() {
+            return Err("The seed has already been revealed");
+        }
+        record.seed_revealed_at = Some(Utc::now());
+        Ok(())
+    }
+
+    fn min_participants(&self) -> usize {
+        self.gifts_per_person + 1
+    }
+
+    pub fn set_gifts_per_person(&mut self, gifts: usize) -> Result<(), &'static str> {
+        if self.status == EventStatus::Closed {
+            return Err("The number of gifts cannot be changed after the draw");
+        }
+        self.check_not_committed()?;
+        if !(1..=MAX_GIFTS_PER_PERSON).contains(&gifts) {
+            return Err("Everyone can give between 1 and 5 gifts");
+        }
+        self.gifts_per_person = gifts;
+        Ok(())
+    }
+
+    fn mark_draw_amended(&mut self) {
+        if let Some(record) = &mut self.draw_record {
+            record.amended_at = Some(Utc::now());
+        }
+    }
+
+    /// Draws names from the published commitment's seed, or from a fresh one
+    /// if none was published, and records how to check the draw.
+    pub fn close_and_assign(&mut self) -> Result<(), AppError> {
+        if self.status == EventStatus::Closed {
+            return Err(AppError::Conflict("Event is already closed".to_string()));
+        }
+        if self.participants.len() < self.min_participants() {
+            return Err(AppError::Conflict(
+                self.describe_draw_error(&DrawError::NotEnoughParticipants),
+            ));
+        }
+
+        let input = self.draw_input();
+        let (seed, commitment, committed_at) = match &self.draw_commitment {
+            Some(committed) => {
+                if committed.seed.is_empty() {
+                    return Err(AppError::Conflict(
+                        "The seed of this commitment was left out of the export it was \
+                         imported from. Withdraw it and publish a new one."
+                            .to_string(),
+                    ));
+                }
+                if verification::commitment(&committed.seed, &input.canonical())
+                    != committed.commitment
+                {
+                    return Err(AppError::Conflict(
+                        "Participants or exclusions changed since the commitment was \
+                         published. Withdraw it and publish a new one."
+                            .to_string(),
+                    ));
+                }
+                (
+                    committed.seed.clone(),
+                    committed.commitment.clone(),
+                    Some(committed.published_at),
+                )
+            }
+            None => {
+                let seed = verification::generate_seed();
+                let commitment = verification::commitment(&seed, &input.canonical());
+                (seed, commitment, None)
+            }
+        };
+
+        let assignments = input.draw(&seed).map_err(|e| {
+            let message = self.describe_draw_error(&e);
+            if self.history_constraint == HistoryConstraint::Hard && !input.history.is_empty() {
+                return AppError::Conflict(format!(
+                    "{}. Pairs from earlier years count as exclusions here, allow repeating \
+                     them if there is no other way.",
+                    message.trim_end_matches('.')
+                ));
+            }
+            AppError::Conflict(message)
+        })?;
+
+        for &(giver, receiver) in &assignments {
             if let Some(participant) = self.participants.get_mut(&giver) {
-                participant.assigned_to = Some(receiver);
+                participant.assigned_to.push(receiver);
             }
         }
 
+        self.draw_record = Some(DrawRecord::new(
+            commitment,
+            committed_at,
+            seed,
+            &input,
+            &assignments,
+        ));
+        self.draw_commitment = None;
         self.status = EventStatus::Closed;
         Ok(())
     }
 
-    pub fn get_assignment(&self, participant_id: Uuid) -> Option<&Participant> {
-        let participant = self.participants.get(&participant_id)?;
-        let assigned_to_id = participant.assigned_to?;
-        self.participants.get(&assigned_to_id)
+    /// Personal link that logs `participant` back in, relative to the site root.
+    pub fn recovery_path(&self, participant: &Participant) -> String {
+        format!("/event/{}/recover/{}", self.id, participant.recovery_token)
+    }
+
+    pub fn participant_by_recovery_token(&self, token: &Uuid) -> Option<&Participant> {
+        self.participants
+            .values()
+            .find(|p| &p.recovery_token == token)
+    }
+
+    fn participant_names(&self, ids: &[Uuid]) -> String {
+        let mut names: Vec<&str> = ids
+            .iter()
+            .filter_map(|id| self.participants.get(id))
+            .map(|p| p.name.as_str())
+            .collect();
+        names.sort_unstable();
+        names.join(", ")
+    }
+
+    pub fn describe_draw_error(&self, error: &DrawError) -> String {
+        match error {
+            DrawError::NotEnoughParticipants => match self.gifts_per_person {
+                1 => "Need at least 2 participants".to_string(),
+                gifts => format!(
+                    "Need at least {} participants for {} gifts per person",
+                    gifts + 1,
+                    gifts
+                ),
+            },
+            DrawError::Infeasible { givers, receivers } if receivers.is_empty() => format!(
+                "No valid draw is possible: the exclusions leave {} nobody to draw",
+                self.participant_names(givers)
+            ),
+            DrawError::Infeasible { givers, receivers } => format!(
+                "No valid draw is possible: {} can only draw from {}, which is not enough \
+                 people for all of them. Remove some exclusions involving them.",
+                self.participant_names(givers),
+                self.participant_names(receivers)
+            ),
+            DrawError::NoSingleCycle => "Could not find a single gift circle through everyone \
+                that respects all exclusions. Remove some exclusions or allow several smaller \
+                circles."
+                .to_string(),
+        }
+    }
+
+    /// Everyone `participant_id` is buying a gift for, in the order drawn.
+    pub fn recipients_of(&self, participant_id: Uuid) -> Vec<&Participant> {
+        self.participants
+            .get(&participant_id)
+            .map(|p| {
+                p.assigned_to
+                    .iter()
+                    .filter_map(|id| self.participants.get(id))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The participants who drew `participant_id`, ordered by id so that
+    /// the order says nothing about who they are.
+    pub fn givers_of(&self, participant_id: Uuid) -> Vec<Uuid> {
+        let mut givers: Vec<Uuid> = self
+            .participants
+            .values()
+            .filter(|p| p.assigned_to.contains(&participant_id))
+            .map(|p| p.id)
+            .collect();
+        givers.sort();
+        givers
+    }
+
+    // The (giver, receiver) pair behind the `index`-th `thread` of
+    // `participant_id`.
+    fn thread_pair(
+        &self,
+        participant_id: Uuid,
+        thread: MessageThread,
+        index: usize,
+    ) -> Option<(Uuid, Uuid)> {
+        match thread {
+            MessageThread::Recipient => {
+                let receiver = *self.participants.get(&participant_id)?.assigned_to.get(index)?;
+                Some((participant_id, receiver))
+            }
+            MessageThread::Wichtel => {
+                Some((*self.givers_of(participant_id).get(index)?, participant_id))
+            }
+        }
+    }
+
+    /// Messages of the current pair behind `thread`, oldest first. Threads of
+    /// pairs that no longer exist are kept but not shown.
+    pub fn thread_messages(
+        &self,
+        participant_id: Uuid,
+        thread: MessageThread,
+        index: usize,
+    ) -> Vec<&Message> {
+        match self.thread_pair(participant_id, thread, index) {
+            Some((giver, receiver)) => self
+                .messages
+                .iter()
+                .filter(|m| m.giver == giver && m.receiver == receiver)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn send_message(
+        &mut self,
+        sender: Uuid,
+        thread: MessageThread,
+        index: usize,
+        body: &str,
+    ) -> Result<(), &'static str> {
+        if self.status != EventStatus::Closed {
+            return Err("Messages can be sent once names are drawn");
+        }
+        let body = body.trim();
+        if body.is_empty() {
+            return Err("Message cannot be empty");
+        }
+        if body.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err("Message is too long");
+        }
+
+        let (giver, receiver) = self
+            .thread_pair(sender, thread, index)
+            .ok_or("Nobody to send this message to")?;
+        if self.thread_messages(sender, thread, index).len() >= MAX_MESSAGES_PER_THREAD {
+            return Err("This conversation has reached its message limit");
+        }
+
+        let author = match thread {
+            MessageThread::Recipient => MessageAuthor::Giver,
+            MessageThread::Wichtel => MessageAuthor::Receiver,
+        };
+        self.messages.push(Message {
+            id: Uuid::new_v4(),
+            giver,
+            receiver,
+            author,
+            body: body.to_string(),
+            sent_at: Utc::now(),
+        });
+        Ok(())
     }
 }
 
-fn generate_invite_code() -> String {
+pub fn generate_invite_code() -> String {
     use rand::Rng;
     const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
     let mut rng = rand::thread_rng();
@@ -94,26 +1314,282 @@ fn generate_invite_code() -> String {
         .collect()
 }
 
-fn generate_assignments(participant_ids: &[Uuid]) -> Result<Vec<(Uuid, Uuid)>, &'static str> {
-    use rand::seq::SliceRandom;
-    
-    if participant_ids.len() < 2 {
-        return Err("Need at least 2 participants");
+fn default_gifts_per_person() -> usize {
+    1
+}
+
+fn generate_pin() -> String {
+    use rand::Rng;
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drawn_event(names: &[&str], gifts: usize) -> WichtelEvent {
+        let mut event = WichtelEvent::new("Office".to_string());
+        for name in names {
+            event.add_participant(name.to_string(), None);
+        }
+        event.set_gifts_per_person(gifts).unwrap();
+        event.close_and_assign().unwrap();
+        event
+    }
+
+    fn id_of(event: &WichtelEvent, name: &str) -> Uuid {
+        event.participants.values().find(|p| p.name == name).unwrap().id
     }
 
-    let mut rng = rand::thread_rng();
-    let mut shuffled = participant_ids.to_vec();
-    
-    // Use derangement algorithm - ensure no one gets themselves
-    loop {
-        shuffled.shuffle(&mut rng);
-        let valid = participant_ids.iter()
-            .zip(shuffled.iter())
-            .all(|(a, b)| a != b);
-        if valid {
-            break;
+    fn assert_valid_draw(event: &WichtelEvent) {
+        let gifts = event.gifts_per_person;
+        for participant in event.participants.values() {
+            let mut recipients = participant.assigned_to.clone();
+            assert_eq!(recipients.len(), gifts, "{} gives {:?}", participant.name, recipients);
+            assert!(!recipients.contains(&participant.id));
+            assert!(recipients.iter().all(|r| event.participants.contains_key(r)));
+            assert!(recipients.iter().all(|&r| !event.is_excluded(participant.id, r)));
+            recipients.sort();
+            recipients.dedup();
+            assert_eq!(recipients.len(), gifts);
+            assert_eq!(event.givers_of(participant.id).len(), gifts);
+        }
+    }
+
+    // Participants whose recipients differ, the ones told about a change.
+    fn changed_givers(before: &WichtelEvent, after: &WichtelEvent) -> Vec<Uuid> {
+        let mut changed: Vec<Uuid> = after
+            .participants
+            .values()
+            .filter(|p| {
+                before
+                    .participants
+                    .get(&p.id)
+                    .is_some_and(|b| b.assigned_to != p.assigned_to)
+            })
+            .map(|p| p.id)
+            .collect();
+        changed.sort();
+        changed
+    }
+
+    // Replaces the draw so that the i-th name draws the next `gifts` names
+    // around the circle, which repair can always handle.
+    fn circle_event(names: &[&str], gifts: usize) -> (WichtelEvent, Vec<Uuid>) {
+        let mut event = drawn_event(names, gifts);
+        let ids: Vec<Uuid> = names.iter().map(|n| id_of(&event, n)).collect();
+        for (i, id) in ids.iter().enumerate() {
+            event.participants.get_mut(id).unwrap().assigned_to =
+                (1..=gifts).map(|k| ids[(i + k) % ids.len()]).collect();
+        }
+        (event, ids)
+    }
+
+    #[test]
+    fn repair_only_changes_the_leavers_givers() {
+        for gifts in [1, 2] {
+            let (before, ids) = circle_event(&["Anna", "Ben", "Clara", "Dora", "Emil", "Fritz"], gifts);
+            let leaver = ids[0];
+            let mut givers = before.givers_of(leaver);
+            givers.sort();
+
+            let mut after = before.clone();
+            after.remove_and_repair(leaver).unwrap();
+
+            assert!(!after.participants.contains_key(&leaver));
+            assert_valid_draw(&after);
+            assert_eq!(changed_givers(&before, &after), givers);
+            for participant in after.participants.values() {
+                assert_eq!(participant.recipient_changed_at.is_some(), givers.contains(&participant.id));
+            }
+            assert!(after.draw_record.as_ref().unwrap().amended_at.is_some());
+        }
+    }
+
+    #[test]
+    fn repair_respects_exclusions() {
+        // Emil drew Anna, who drew Ben.
+        let (mut event, ids) = circle_event(&["Anna", "Ben", "Clara", "Dora", "Emil"], 1);
+        event.exclusions.push(Exclusion::Pair {
+            id: Uuid::new_v4(),
+            giver: ids[4],
+            receiver: ids[1],
+            mutual: false,
+        });
+        let before = event.clone();
+
+        assert!(matches!(event.remove_and_repair(ids[0]), Err(AppError::Conflict(_))));
+        assert_eq!(changed_givers(&before, &event), Vec::<Uuid>::new());
+        assert_eq!(event.participants.len(), 5);
+    }
+
+    #[test]
+    fn repair_refuses_to_leave_someone_drawing_themselves() {
+        let mut event = drawn_event(&["Anna", "Ben", "Clara", "Dora"], 1);
+        let ids: Vec<Uuid> = ["Anna", "Ben", "Clara", "Dora"].iter().map(|n| id_of(&event, n)).collect();
+        // Anna and Ben drew each other, as did Clara and Dora.
+        for (giver, receiver) in [(0, 1), (1, 0), (2, 3), (3, 2)] {
+            event.participants.get_mut(&ids[giver]).unwrap().assigned_to = vec![ids[receiver]];
+        }
+        let before = event.clone();
+
+        assert!(matches!(event.remove_and_repair(ids[0]), Err(AppError::Conflict(_))));
+        assert_eq!(changed_givers(&before, &event), Vec::<Uuid>::new());
+        assert_eq!(event.participants.len(), 4);
+    }
+
+    #[test]
+    fn late_join_splices_into_as_many_pairs_as_gifts() {
+        for gifts in [1, 2] {
+            let mut before = drawn_event(&["Anna", "Ben", "Clara", "Dora", "Emil"], gifts);
+            before.late_join = true;
+
+            let mut after = before.clone();
+            let newcomer = after.join("Gert".to_string(), None).unwrap();
+
+            assert_valid_draw(&after);
+            let mut givers = after.givers_of(newcomer);
+            givers.sort();
+            assert_eq!(givers.len(), gifts);
+            assert_eq!(changed_givers(&before, &after), givers);
+            // The newcomer took over exactly the recipients those givers had.
+            let mut handed_over: Vec<Uuid> = givers
+                .iter()
+                .flat_map(|g| {
+                    let old = &before.participants[g].assigned_to;
+                    let new = &after.participants[g].assigned_to;
+                    old.iter().filter(|r| !new.contains(r)).copied().collect::<Vec<_>>()
+                })
+                .collect();
+            let mut newcomer_draws = after.participants[&newcomer].assigned_to.clone();
+            handed_over.sort();
+            newcomer_draws.sort();
+            assert_eq!(handed_over, newcomer_draws);
+        }
+    }
+
+    #[test]
+    fn late_join_needs_to_be_enabled() {
+        let mut event = drawn_event(&["Anna", "Ben", "Clara"], 1);
+        assert!(event.join("Gert".to_string(), None).is_err());
+        assert_eq!(event.participants.len(), 3);
+    }
+
+    fn import(event: &mut WichtelEvent, list: &str) -> Result<Vec<Uuid>, ImportError> {
+        event.import_participants(crate::import::parse(list).unwrap())
+    }
+
+    #[test]
+    fn import_adds_everyone_on_the_list() {
+        let mut event = WichtelEvent::new("Office".to_string());
+        event.add_participant("Anna".to_string(), None);
+        let ids = import(&mut event, "Ben <ben@example.com>\nClara\n").unwrap();
+
+        assert_eq!(ids.len(), 2);
+        assert_eq!(event.participants.len(), 3);
+        let ben = &event.participants[&ids[0]];
+        assert_eq!(ben.name, "Ben");
+        assert_eq!(ben.email.as_deref(), Some("ben@example.com"));
+        assert_eq!(event.participants[&ids[1]].name, "Clara");
+        assert!(event.exclusions.is_empty());
+    }
+
+    #[test]
+    fn import_rejects_duplicate_names_without_adding_anyone() {
+        let mut event = WichtelEvent::new("Office".to_string());
+        event.add_participant("Anna".to_string(), None);
+
+        let error = import(&mut event, "Ben\nClara\n  ben \n").unwrap_err();
+        assert_eq!(error.line, Some(3));
+        assert_eq!(error.message, "{name} is on the list twice");
+
+        let error = import(&mut event, "Ben\nANNA\n").unwrap_err();
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.message, "{name} is already taking part");
+        assert_eq!(error.name, "ANNA");
+
+        assert_eq!(event.participants.len(), 1);
+    }
+
+    #[test]
+    fn import_turns_exclusions_into_mutual_pairs() {
+        let mut event = WichtelEvent::new("Office".to_string());
+        event.add_participant("Anna".to_string(), None);
+        let list = "name;exclusions\nBen;Clara, anna\nClara;Ben\nDora;\n";
+        import(&mut event, list).unwrap();
+        let (anna, ben, clara) = (id_of(&event, "Anna"), id_of(&event, "Ben"), id_of(&event, "Clara"));
+
+        // Ben and Clara list each other, which is one exclusion.
+        assert_eq!(event.exclusions.len(), 2);
+        for (a, b) in [(ben, clara), (ben, anna)] {
+            assert!(event.exclusions.iter().any(|e| e.forbids(a, b) && e.forbids(b, a)));
         }
+        assert!(!event.exclusions.iter().any(|e| e.forbids(anna, clara)));
+    }
+
+    #[test]
+    fn import_rejects_unknown_and_own_exclusions() {
+        let mut event = WichtelEvent::new("Office".to_string());
+        let error = import(&mut event, "name;exclusions\nBen;Gert\n").unwrap_err();
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.message, "Nobody called {name} is taking part");
+        assert_eq!(error.name, "Gert");
+
+        let error = import(&mut event, "name;exclusions\nBen;ben\n").unwrap_err();
+        assert_eq!(error.message, "{name} cannot exclude themselves");
+        assert!(event.participants.is_empty());
+        assert!(event.exclusions.is_empty());
     }
 
-    Ok(participant_ids.iter().cloned().zip(shuffled.into_iter()).collect())
+    #[test]
+    fn import_merges_groups_by_label() {
+        let mut event = WichtelEvent::new("Office".to_string());
+        event.add_participant("Anna".to_string(), None);
+        let household = Uuid::from_u128(1);
+        event.exclusions.push(Exclusion::Group {
+            id: household,
+            label: "Schmidt".to_string(),
+            members: vec![id_of(&event, "Anna")],
+        });
+
+        let list = "name;group\nBen;schmidt\nClara;Müller\nDora;Müller\nEmil;Single\n";
+        import(&mut event, list).unwrap();
+        let members = |label: &str| -> Vec<Uuid> {
+            event
+                .exclusions
+                .iter()
+                .find_map(|e| match e {
+                    Exclusion::Group { label: l, members, .. } if l == label => Some(members.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        assert_eq!(event.exclusions.len(), 2);
+        assert_eq!(event.exclusions[0].id(), household);
+        assert_eq!(members("Schmidt"), [id_of(&event, "Anna"), id_of(&event, "Ben")]);
+        assert_eq!(members("Müller"), [id_of(&event, "Clara"), id_of(&event, "Dora")]);
+    }
+
+    #[test]
+    fn import_is_refused_after_the_draw() {
+        let mut event = drawn_event(&["Anna", "Ben", "Clara"], 1);
+        assert!(import(&mut event, "Dora\n").is_err());
+        assert_eq!(event.participants.len(), 3);
+    }
+
+    #[test]
+    fn registration_deadline_must_be_in_the_future() {
+        let mut event = WichtelEvent::new("Office".to_string());
+        let now = Utc::now();
+        assert!(event.set_registration_deadline(Some(now)).is_err());
+        assert!(event.set_registration_deadline(Some(now - chrono::Duration::hours(1))).is_err());
+        assert_eq!(event.registration_deadline, None);
+
+        let tomorrow = now + chrono::Duration::days(1);
+        event.set_registration_deadline(Some(tomorrow)).unwrap();
+        assert_eq!(event.registration_deadline, Some(tomorrow));
+        event.set_registration_deadline(None).unwrap();
+        assert_eq!(event.registration_deadline, None);
+    }
 }
@@ -0,0 +1,72 @@
+use image::{ImageFormat, Luma};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use std::io::Cursor;
+use std::str::FromStr;
+
+// Big enough to scan from a printout across the table.
+const MIN_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrFormat {
+    Svg,
+    Png,
+}
+
+impl QrFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            QrFormat::Svg => "image/svg+xml",
+            QrFormat::Png => "image/png",
+        }
+    }
+}
+
+impl FromStr for QrFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(QrFormat::Svg),
+            "png" => Ok(QrFormat::Png),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Renders QR codes of the app's links. Phones need the full URL, so paths
+/// are put behind the public base URL.
+pub struct QrCodes {
+    public_url: String,
+}
+
+impl QrCodes {
+    pub fn new(public_url: &str) -> Self {
+        Self {
+            public_url: public_url.to_string(),
+        }
+    }
+
+    pub fn render(&self, path: &str, format: QrFormat) -> Result<Vec<u8>, String> {
+        let url = format!("{}{}", self.public_url, path);
+        let code = QrCode::new(url.as_bytes()).map_err(|e| e.to_string())?;
+        match format {
+            QrFormat::Svg => Ok(code
+                .render::<svg::Color>()
+                .min_dimensions(MIN_SIZE, MIN_SIZE)
+                .build()
+                .into_bytes()),
+            QrFormat::Png => {
+                let image = code
+                    .render::<Luma<u8>>()
+                    .min_dimensions(MIN_SIZE, MIN_SIZE)
+                    .build();
+                let mut png = Vec::new();
+                image
+                    .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                    .map_err(|e| e.to_string())?;
+                Ok(png)
+            }
+        }
+    }
+}
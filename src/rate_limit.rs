@@ -0,0 +1,238 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderName;
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpRequest};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::LimitsConfig;
+use crate::error::AppError;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+// Expired windows are only swept out once there are this many, so a normal
+// request doesn't pay for walking the whole map.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// The unauthenticated endpoints worth throttling. Each has its own budget,
+/// so searching for your name doesn't keep you from joining.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Endpoint {
+    Create,
+    Join,
+    Search,
+}
+
+impl Endpoint {
+    fn of(req: &ServiceRequest) -> Option<Self> {
+        let path = req.path();
+        let post = req.method() == Method::POST;
+        let creates = ["/create", "/import", "/api/v1/events", "/api/v1/events/import"];
+        if post && creates.contains(&path) {
+            Some(Endpoint::Create)
+        } else if path.starts_with("/join/") || path.starts_with("/api/v1/join/") {
+            Some(Endpoint::Join)
+        } else if path.starts_with("/event/") && path.ends_with("/search") {
+            Some(Endpoint::Search)
+        } else {
+            None
+        }
+    }
+}
+
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+// Counts a hit at `now` for `key` in a fixed window of `length`. Returns how
+// long until the window resets if `limit` hits were already counted in it.
+fn hit<K: Hash + Eq>(
+    windows: &mut HashMap<K, Window>,
+    key: K,
+    limit: u32,
+    length: Duration,
+    now: Instant,
+) -> Result<(), Duration> {
+    if windows.len() >= PRUNE_THRESHOLD {
+        windows.retain(|_, w| now.duration_since(w.started) < length);
+    }
+
+    let window = windows.entry(key).or_insert(Window {
+        started: now,
+        count: 0,
+    });
+    if now.duration_since(window.started) >= length {
+        *window = Window {
+            started: now,
+            count: 0,
+        };
+    }
+    if window.count >= limit {
+        return Err(length - now.duration_since(window.started));
+    }
+    window.count += 1;
+    Ok(())
+}
+
+/// Per-IP request and event creation budgets. Kept in memory only, a restart
+/// simply resets them.
+pub struct RateLimiter {
+    requests_per_minute: u32,
+    events_per_day: u32,
+    client_ip_header: Option<HeaderName>,
+    requests: Mutex<HashMap<(IpAddr, Endpoint), Window>>,
+    created_events: Mutex<HashMap<IpAddr, Window>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &LimitsConfig) -> Result<Self, String> {
+        let client_ip_header = config
+            .client_ip_header
+            .as_deref()
+            .map(|h| {
+                HeaderName::try_from(h.trim())
+                    .map_err(|_| format!("Invalid TRUSTED_PROXY_HEADER '{}'", h))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            requests_per_minute: config.requests_per_minute,
+            events_per_day: config.events_per_ip_per_day,
+            client_ip_header,
+            requests: Mutex::new(HashMap::new()),
+            created_events: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The client's address: from the trusted proxy header if one is
+    /// configured and present, otherwise the connecting peer. Proxies append
+    /// to `X-Forwarded-For`, so only its last entry is trustworthy.
+    fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let forwarded = self
+            .client_ip_header
+            .as_ref()
+            .and_then(|name| req.headers().get(name))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        forwarded.or_else(|| req.peer_addr().map(|addr| addr.ip()))
+    }
+
+    /// Counts a new event against the creator's daily budget.
+    pub fn check_new_event(&self, req: &HttpRequest) -> Result<(), AppError> {
+        if self.events_per_day == 0 {
+            return Ok(());
+        }
+        let Some(ip) = self.client_ip(req) else {
+            return Ok(());
+        };
+
+        let mut created_events = self.created_events.lock();
+        hit(&mut created_events, ip, self.events_per_day, DAY, Instant::now()).map_err(|retry_after| {
+            log::warn!("Rejected event creation from {}: daily limit reached", ip);
+            AppError::TooManyRequests {
+                message: "You have created too many events today. Please try again tomorrow.",
+                retry_after,
+            }
+        })
+    }
+}
+
+/// Throttles creating events, joining and searching participants per client
+/// IP.
+pub async fn limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let rejected = limiter
+        .filter(|limiter| limiter.requests_per_minute > 0)
+        .zip(Endpoint::of(&req))
+        .and_then(|(limiter, endpoint)| {
+            let ip = limiter.client_ip(req.request())?;
+            let mut requests = limiter.requests.lock();
+            hit(&mut requests, (ip, endpoint), limiter.requests_per_minute, MINUTE, Instant::now())
+                .err()
+                .map(|retry_after| (ip, retry_after))
+        });
+
+    match rejected {
+        None => next.call(req).await.map(ServiceResponse::map_into_left_body),
+        Some((ip, retry_after)) => {
+            log::warn!("Rate limit exceeded by {}: {} {}", ip, req.method(), req.path());
+            Ok(req
+                .error_response(AppError::TooManyRequests {
+                    message: "Too many requests. Please wait a moment and try again.",
+                    retry_after,
+                })
+                .map_into_right_body())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn limiter(events_per_day: u32) -> RateLimiter {
+        RateLimiter::new(&LimitsConfig {
+            requests_per_minute: 30,
+            client_ip_header: Some("X-Forwarded-For".to_string()),
+            events_per_ip_per_day: events_per_day,
+            participants_per_event: 10,
+        })
+        .unwrap()
+    }
+
+    fn request_from(ip: &str) -> HttpRequest {
+        TestRequest::default()
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", format!("1.2.3.4, {}", ip)))
+            .to_http_request()
+    }
+
+    #[test]
+    fn windows_allow_the_limit_and_then_reset() {
+        let mut windows = HashMap::new();
+        let start = Instant::now();
+        for i in 0..3 {
+            assert!(hit(&mut windows, "a", 3, MINUTE, start + Duration::from_secs(i)).is_ok());
+        }
+
+        let later = start + Duration::from_secs(20);
+        assert_eq!(hit(&mut windows, "a", 3, MINUTE, later), Err(Duration::from_secs(40)));
+        assert!(hit(&mut windows, "b", 3, MINUTE, later).is_ok());
+
+        let next_window = start + MINUTE;
+        assert!(hit(&mut windows, "a", 3, MINUTE, next_window).is_ok());
+        assert_eq!(windows["a"].count, 1);
+    }
+
+    #[test]
+    fn event_creation_is_limited_per_client() {
+        let limiter = limiter(2);
+        for _ in 0..2 {
+            assert!(limiter.check_new_event(&request_from("5.6.7.8")).is_ok());
+        }
+        assert!(matches!(
+            limiter.check_new_event(&request_from("5.6.7.8")),
+            Err(AppError::TooManyRequests { .. })
+        ));
+        assert!(limiter.check_new_event(&request_from("5.6.7.9")).is_ok());
+    }
+
+    #[test]
+    fn zero_disables_the_event_limit() {
+        let limiter = limiter(0);
+        for _ in 0..100 {
+            assert!(limiter.check_new_event(&request_from("5.6.7.8")).is_ok());
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::mailer::Mailer;
+use crate::state::AppState;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Closes events whose registration deadline has passed. Deadlines live in the
+/// store, so anything that came due while the server was down is picked up on
+/// the first check after a restart.
+pub async fn run(state: Arc<AppState>, mailer: Mailer) {
+    let mut interval = actix_web::rt::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        for (event_id, result) in state.close_due_events(Utc::now()) {
+            match result {
+                Ok(()) => {
+                    log::info!("Registration deadline passed, drew names for event {}", event_id);
+                    if let Some(event) = state.get_event(&event_id) {
+                        mailer.send_draw_notifications(&event);
+                    }
+                }
+                Err(e) => log::warn!(
+                    "Registration deadline passed, but drawing names for event {} failed: {}",
+                    event_id,
+                    e
+                ),
+            }
+        }
+    }
+}
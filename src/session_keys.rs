@@ -0,0 +1,156 @@
+use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+use actix_web::body::MessageBody;
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderValue, COOKIE};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::config::{Environment, SessionKeyConfig};
+
+pub const SESSION_COOKIE_NAME: &str = "id";
+
+#[derive(Clone)]
+pub struct SessionKeys {
+    pub current: Key,
+    pub previous: Vec<Key>,
+}
+
+fn parse_key(encoded: &str) -> Result<Key, String> {
+    let bytes = STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| format!("session key is not valid base64: {}", e))?;
+    Key::try_from(bytes.as_slice())
+        .map_err(|_| format!("session key must be at least 64 bytes, got {}", bytes.len()))
+}
+
+impl SessionKeys {
+    pub fn load(config: &SessionKeyConfig, environment: Environment) -> Result<Self, String> {
+        let encoded = match (&config.key, &config.key_file) {
+            (Some(_), Some(_)) => {
+                return Err("Set only one of SESSION_KEY and SESSION_KEY_FILE".to_string())
+            }
+            (Some(key), None) => Some(key.clone()),
+            (None, Some(path)) => Some(std::fs::read_to_string(path).map_err(|e| {
+                format!("Failed to read session key file {}: {}", path.display(), e)
+            })?),
+            (None, None) => None,
+        };
+
+        let current = match encoded {
+            Some(encoded) => parse_key(&encoded)?,
+            None if environment == Environment::Production => {
+                return Err(
+                    "Refusing to start in production without SESSION_KEY or SESSION_KEY_FILE"
+                        .to_string(),
+                )
+            }
+            None => {
+                log::warn!(
+                    "No session key configured, generating an ephemeral one. \
+                     Participant sessions will not survive a restart."
+                );
+                Key::generate()
+            }
+        };
+
+        let previous = config
+            .previous_keys
+            .iter()
+            .map(|k| parse_key(k))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid entry in SESSION_PREVIOUS_KEYS: {}", e))?;
+
+        Ok(Self { current, previous })
+    }
+
+    pub fn session_middleware(&self) -> SessionMiddleware<CookieSessionStore> {
+        SessionMiddleware::builder(CookieSessionStore::default(), self.current.clone())
+            .cookie_name(SESSION_COOKIE_NAME.to_string())
+            .build()
+    }
+
+    // Returns the cookie value re-encrypted with the current key if it was
+    // produced with one of the previous keys.
+    fn reencrypt(&self, value: &str) -> Option<String> {
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new(SESSION_COOKIE_NAME, value.to_owned()));
+
+        if jar.private(&self.current).get(SESSION_COOKIE_NAME).is_some() {
+            return None;
+        }
+
+        let plain = self
+            .previous
+            .iter()
+            .find_map(|key| jar.private(key).get(SESSION_COOKIE_NAME))?;
+
+        let mut rotated = CookieJar::new();
+        rotated.private_mut(&self.current).add(plain);
+        rotated
+            .get(SESSION_COOKIE_NAME)
+            .map(|c| c.value().to_owned())
+    }
+}
+
+/// Lets sessions created under a previous key keep working: the session
+/// cookie is re-encrypted with the current key before the session middleware
+/// sees it, and the upgraded cookie is sent back to the browser.
+pub async fn rotate_session_cookie(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let keys = req.app_data::<web::Data<SessionKeys>>().cloned();
+    let mut rotated_value = None;
+
+    if let Some(keys) = keys.filter(|k| !k.previous.is_empty()) {
+        let mut cookies: Vec<Cookie<'static>> = req
+            .headers()
+            .get_all(COOKIE)
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(';'))
+            .filter_map(|c| Cookie::parse_encoded(c.trim().to_owned()).ok())
+            .collect();
+
+        for cookie in cookies.iter_mut() {
+            if cookie.name() == SESSION_COOKIE_NAME {
+                if let Some(value) = keys.reencrypt(cookie.value()) {
+                    cookie.set_value(value.clone());
+                    rotated_value = Some(value);
+                }
+            }
+        }
+
+        if rotated_value.is_some() {
+            let header = cookies
+                .iter()
+                .map(|c| c.encoded().to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            if let Ok(header) = HeaderValue::from_str(&header) {
+                req.headers_mut().insert(COOKIE, header);
+            }
+        }
+    }
+
+    let mut res = next.call(req).await?;
+
+    if let Some(value) = rotated_value {
+        let already_set = res
+            .response()
+            .cookies()
+            .any(|c| c.name() == SESSION_COOKIE_NAME);
+        if !already_set {
+            let cookie = Cookie::build(SESSION_COOKIE_NAME, value)
+                .path("/")
+                .secure(true)
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .finish();
+            let _ = res.response_mut().add_cookie(&cookie);
+        }
+    }
+
+    Ok(res)
+}
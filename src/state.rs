@@ -1,62 +1,601 @@
-use parking_lot::RwLock;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use parking_lot::Mutex;
 use std::collections::HashMap;
 use uuid::Uuid;
 
-use crate::models::WichtelEvent;
+use crate::budget::Budget;
+use crate::config::DEFAULT_MAX_PARTICIPANTS;
+use crate::csrf;
+use crate::draw::{DrawMode, HistoryConstraint};
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::import::{ImportError, ImportRow};
+use crate::models::{generate_invite_code, Exclusion, MessageThread, WichtelEvent, Wishlist};
+use crate::store::{EventStore, MemoryStore};
+
+const MAX_PIN_ATTEMPTS: u32 = 5;
+const PIN_LOCKOUT_MINUTES: i64 = 15;
+
+struct FailedAttempts {
+    count: u32,
+    since: DateTime<Utc>,
+}
 
 pub struct AppState {
-    pub events: RwLock<HashMap<Uuid, WichtelEvent>>,
-    pub invite_codes: RwLock<HashMap<String, Uuid>>,
+    store: Box<dyn EventStore>,
+    // Failed PIN entries per event and participant. Participant ids repeat
+    // in cloned and imported events, so they alone are not enough. Kept in
+    // memory only, a restart simply resets the counters.
+    pin_attempts: Mutex<HashMap<(Uuid, Uuid), FailedAttempts>>,
+    // Zero means no limit.
+    max_participants: usize,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(store: Box<dyn EventStore>, max_participants: usize) -> Self {
         Self {
-            events: RwLock::new(HashMap::new()),
-            invite_codes: RwLock::new(HashMap::new()),
+            store,
+            pin_attempts: Mutex::new(HashMap::new()),
+            max_participants,
         }
     }
 
-    pub fn create_event(&self, name: String) -> WichtelEvent {
-        let event = WichtelEvent::new(name);
-        let mut events = self.events.write();
-        let mut codes = self.invite_codes.write();
-        
-        codes.insert(event.invite_code.clone(), event.id);
-        events.insert(event.id, event.clone());
-        event
+    pub fn create_event(&self, mut event: WichtelEvent) -> Option<WichtelEvent> {
+
+        // Invite codes are short, so make sure we don't hand out one that is taken
+        loop {
+            match self.store.get_event_by_invite_code(&event.invite_code) {
+                Ok(None) => break,
+                Ok(Some(_)) => event.invite_code = generate_invite_code(),
+                Err(e) => {
+                    log::error!("Failed to check invite code: {}", e);
+                    return None;
+                }
+            }
+        }
+
+        match self.store.insert_event(&event) {
+            Ok(()) => Some(event),
+            Err(e) => {
+                log::error!("Failed to store event {}: {}", event.id, e);
+                None
+            }
+        }
+    }
+
+    /// Stores an event exported elsewhere under its own id, so the links of
+    /// its organizer and participants keep working. Only the invite code may
+    /// change, if it is taken here.
+    pub fn import_event(&self, event: WichtelEvent) -> Result<WichtelEvent, AppError> {
+        if self.max_participants > 0 && event.participants.len() > self.max_participants {
+            return Err(AppError::Conflict(
+                "This event has more participants than allowed here".to_string(),
+            ));
+        }
+        match self.store.get_event(&event.id) {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                return Err(AppError::Conflict("This event already exists here".to_string()));
+            }
+            Err(e) => {
+                log::error!("Failed to check for event {}: {}", event.id, e);
+                return Err(AppError::Internal);
+            }
+        }
+        self.create_event(event).ok_or(AppError::Internal)
     }
 
     pub fn get_event(&self, id: &Uuid) -> Option<WichtelEvent> {
-        self.events.read().get(id).cloned()
+        self.store.get_event(id).unwrap_or_else(|e| {
+            log::error!("Failed to load event {}: {}", id, e);
+            None
+        })
     }
 
     pub fn get_event_by_invite_code(&self, code: &str) -> Option<WichtelEvent> {
-        let codes = self.invite_codes.read();
-        let event_id = codes.get(code)?;
-        self.get_event(event_id)
+        self.store.get_event_by_invite_code(code).unwrap_or_else(|e| {
+            log::error!("Failed to load event by invite code: {}", e);
+            None
+        })
+    }
+
+    pub fn add_participant(
+        &self,
+        event_id: &Uuid,
+        name: String,
+        email: Option<String>,
+    ) -> Result<Uuid, &'static str> {
+        let max_participants = self.max_participants;
+        self.modify_event(event_id, |event| {
+            if max_participants > 0 && event.participants.len() >= max_participants {
+                log::warn!("Event {} is full, rejected a new participant", event.id);
+                return Err("This event has reached the maximum number of participants");
+            }
+
+            event.join(name, email)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn import_participants(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        rows: Vec<ImportRow>,
+    ) -> Result<Vec<Uuid>, ImportError> {
+        let max_participants = self.max_participants;
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token".into());
+            }
+            if max_participants > 0 && event.participants.len() + rows.len() > max_participants {
+                log::warn!("Event {} is full, rejected an import of {} participants", event.id, rows.len());
+                return Err("The list is longer than there is room for in this event".into());
+            }
+
+            event.import_participants(rows)
+        })
+        .unwrap_or(Err("Event not found".into()))
     }
 
-    pub fn add_participant(&self, event_id: &Uuid, name: String) -> Option<Uuid> {
-        let mut events = self.events.write();
-        let event = events.get_mut(event_id)?;
-        Some(event.add_participant(name))
+    pub fn set_email(
+        &self,
+        event_id: &Uuid,
+        participant_id: Uuid,
+        email: Option<String>,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| event.set_email(participant_id, email))
+            .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn set_late_join(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        enabled: bool,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.late_join = enabled;
+            Ok(())
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn set_language(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        language: Option<Locale>,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.language = language;
+            Ok(())
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn close_event(&self, event_id: &Uuid, organizer_token: &Uuid) -> Result<(), AppError> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err(AppError::Forbidden("Invalid organizer token"));
+            }
+
+            event.close_and_assign()
+        })
+        .unwrap_or(Err(AppError::NotFound("Event not found")))
     }
 
-    pub fn close_event(&self, event_id: &Uuid, organizer_token: &Uuid) -> Result<(), &'static str> {
-        let mut events = self.events.write();
-        let event = events.get_mut(event_id).ok_or("Event not found")?;
-        
+    pub fn publish_commitment(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.publish_commitment()
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn withdraw_commitment(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.withdraw_commitment()
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn reveal_seed(&self, event_id: &Uuid, organizer_token: &Uuid) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.reveal_seed()
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn rename_participant(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        participant_id: Uuid,
+        name: &str,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.rename_participant(participant_id, name)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn remove_participant(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        participant_id: Uuid,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.remove_participant(participant_id)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn merge_participants(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        keep: Uuid,
+        duplicate: Uuid,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.merge_participants(keep, duplicate)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn remove_and_repair(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        participant_id: Uuid,
+    ) -> Result<(), AppError> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err(AppError::Forbidden("Invalid organizer token"));
+            }
+
+            event.remove_and_repair(participant_id)
+        })
+        .unwrap_or(Err(AppError::NotFound("Event not found")))
+    }
+
+    pub fn remove_and_redraw(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        participant_id: Uuid,
+    ) -> Result<(), AppError> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err(AppError::Forbidden("Invalid organizer token"));
+            }
+
+            event.remove_and_redraw(participant_id)
+        })
+        .unwrap_or(Err(AppError::NotFound("Event not found")))
+    }
+
+    pub fn add_exclusion(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        exclusion: Exclusion,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.add_exclusion(exclusion)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn update_wishlist(
+        &self,
+        event_id: &Uuid,
+        participant_id: Uuid,
+        wishlist: Wishlist,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| event.update_wishlist(participant_id, wishlist))
+            .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn send_message(
+        &self,
+        event_id: &Uuid,
+        sender: Uuid,
+        thread: MessageThread,
+        index: usize,
+        body: &str,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| event.send_message(sender, thread, index, body))
+            .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn set_registration_deadline(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        deadline: Option<DateTime<Utc>>,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.set_registration_deadline(deadline)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    /// Draws names for every event whose registration deadline has passed.
+    /// Returns the events that were due together with the outcome.
+    pub fn close_due_events(&self, now: DateTime<Utc>) -> Vec<(Uuid, Result<(), AppError>)> {
+        let due = match self.store.events_due_for_auto_close(now) {
+            Ok(ids) => ids,
+            Err(e) => {
+                log::error!("Failed to look up events due for closing: {}", e);
+                return Vec::new();
+            }
+        };
+
+        due.into_iter()
+            .filter_map(|id| {
+                let result = self.modify_event(&id, |event| event.auto_close(now))??;
+                Some((id, result))
+            })
+            .collect()
+    }
+
+    pub fn set_budget(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        budget: Option<Budget>,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.budget = budget;
+            Ok(())
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn set_exchange_date(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        date: Option<NaiveDate>,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.exchange_date = date;
+            Ok(())
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn set_gifts_per_person(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        gifts: usize,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.set_gifts_per_person(gifts)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    /// Starts the next round of the series `event_id` belongs to.
+    pub fn clone_event(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        name: &str,
+    ) -> Result<WichtelEvent, &'static str> {
+        let event = self.get_event(event_id).ok_or("Event not found")?;
         if &event.organizer_token != organizer_token {
             return Err("Invalid organizer token");
         }
-        
-        event.close_and_assign()
+
+        let next = event.next_year(name)?;
+        self.create_event(next).ok_or("Failed to create event")
+    }
+
+    pub fn set_history(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        years: usize,
+        constraint: HistoryConstraint,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.set_history(years, constraint)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn set_draw_mode(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        mode: DrawMode,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.set_draw_mode(mode)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn remove_exclusion(
+        &self,
+        event_id: &Uuid,
+        organizer_token: &Uuid,
+        exclusion_id: Uuid,
+    ) -> Result<(), &'static str> {
+        self.modify_event(event_id, |event| {
+            if &event.organizer_token != organizer_token {
+                return Err("Invalid organizer token");
+            }
+
+            event.remove_exclusion(exclusion_id)
+        })
+        .unwrap_or(Err("Event not found"))
+    }
+
+    pub fn verify_pin(
+        &self,
+        event: &WichtelEvent,
+        participant_id: &Uuid,
+        pin: &str,
+    ) -> Result<(), &'static str> {
+        let participant = event
+            .participants
+            .get(participant_id)
+            .ok_or("Participant not found in this event")?;
+
+        let key = (event.id, *participant_id);
+        let mut attempts = self.pin_attempts.lock();
+        let now = Utc::now();
+        let window = Duration::minutes(PIN_LOCKOUT_MINUTES);
+        attempts.retain(|_, a| now - a.since < window);
+
+        if let Some(a) = attempts.get(&key) {
+            if a.count >= MAX_PIN_ATTEMPTS {
+                return Err("Too many wrong PINs for this participant. Please try again later.");
+            }
+        }
+
+        if csrf::tokens_match(&participant.pin, pin.trim()) {
+            attempts.remove(&key);
+            return Ok(());
+        }
+
+        let entry = attempts.entry(key).or_insert(FailedAttempts {
+            count: 0,
+            since: now,
+        });
+        entry.count += 1;
+        log::warn!(
+            "Wrong PIN for participant {} of event {} ({} of {})",
+            participant_id,
+            event.id,
+            entry.count,
+            MAX_PIN_ATTEMPTS
+        );
+        Err("Wrong PIN")
+    }
+
+    // Runs `f` against the stored event and persists the result. Returns `None`
+    // if the event does not exist or the store failed.
+    fn modify_event<T>(&self, id: &Uuid, f: impl FnOnce(&mut WichtelEvent) -> T) -> Option<T> {
+        let mut f = Some(f);
+        let mut result = None;
+        let found = self.store.update_event(id, &mut |event| {
+            if let Some(f) = f.take() {
+                result = Some(f(event));
+            }
+        });
+
+        match found {
+            Ok(true) => result,
+            Ok(false) => None,
+            Err(e) => {
+                log::error!("Failed to update event {}: {}", id, e);
+                None
+            }
+        }
     }
 }
 
 impl Default for AppState {
     fn default() -> Self {
-        Self::new()
+        Self::new(Box::new(MemoryStore::new()), DEFAULT_MAX_PARTICIPANTS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pin_lockout_stays_with_its_event() {
+        let state = AppState::default();
+        let mut event = WichtelEvent::new("Office".to_string());
+        let anna = event.add_participant("Anna".to_string(), None);
+        event.add_participant("Ben".to_string(), None);
+        let copy = event.next_year("Office next year").unwrap();
+        assert!(copy.participants.contains_key(&anna));
+
+        let wrong = if event.participants[&anna].pin == "000000" { "111111" } else { "000000" };
+        for _ in 0..MAX_PIN_ATTEMPTS {
+            assert_eq!(state.verify_pin(&event, &anna, wrong), Err("Wrong PIN"));
+        }
+        let pin = event.participants[&anna].pin.clone();
+        assert!(state.verify_pin(&event, &anna, &pin).is_err());
+
+        let pin = copy.participants[&anna].pin.clone();
+        assert_eq!(state.verify_pin(&copy, &anna, &pin), Ok(()));
     }
 }
@@ -0,0 +1,81 @@
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use super::{EventStore, StoreError};
+use crate::models::WichtelEvent;
+
+pub struct MemoryStore {
+    events: RwLock<HashMap<Uuid, WichtelEvent>>,
+    invite_codes: RwLock<HashMap<String, Uuid>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            events: RwLock::new(HashMap::new()),
+            invite_codes: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventStore for MemoryStore {
+    fn insert_event(&self, event: &WichtelEvent) -> Result<(), StoreError> {
+        let mut events = self.events.write();
+        let mut codes = self.invite_codes.write();
+
+        codes.insert(event.invite_code.clone(), event.id);
+        events.insert(event.id, event.clone());
+        Ok(())
+    }
+
+    fn get_event(&self, id: &Uuid) -> Result<Option<WichtelEvent>, StoreError> {
+        Ok(self.events.read().get(id).cloned())
+    }
+
+    fn get_event_by_invite_code(&self, code: &str) -> Result<Option<WichtelEvent>, StoreError> {
+        let codes = self.invite_codes.read();
+        match codes.get(code) {
+            Some(event_id) => self.get_event(event_id),
+            None => Ok(None),
+        }
+    }
+
+    fn update_event(
+        &self,
+        id: &Uuid,
+        f: &mut dyn FnMut(&mut WichtelEvent),
+    ) -> Result<bool, StoreError> {
+        let mut events = self.events.write();
+        let event = match events.get_mut(id) {
+            Some(e) => e,
+            None => return Ok(false),
+        };
+
+        let old_code = event.invite_code.clone();
+        f(event);
+
+        if event.invite_code != old_code {
+            let mut codes = self.invite_codes.write();
+            codes.remove(&old_code);
+            codes.insert(event.invite_code.clone(), event.id);
+        }
+        Ok(true)
+    }
+    fn events_due_for_auto_close(&self, now: DateTime<Utc>) -> Result<Vec<Uuid>, StoreError> {
+        Ok(self
+            .events
+            .read()
+            .values()
+            .filter(|e| e.pending_auto_close().is_some_and(|at| at <= now))
+            .map(|e| e.id)
+            .collect())
+    }
+}
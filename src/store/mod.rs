@@ -0,0 +1,61 @@
+mod memory;
+mod sqlite;
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::models::WichtelEvent;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Sqlite(rusqlite::Error),
+    Serialization(serde_json::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+            StoreError::Serialization(e) => write!(f, "Serialization error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Serialization(e)
+    }
+}
+
+/// Persistence backend for events. Implementations must make `update_event`
+/// atomic: the closure sees the latest stored version and its changes are
+/// written back before any other update of the same event starts.
+pub trait EventStore: Send + Sync {
+    fn insert_event(&self, event: &WichtelEvent) -> Result<(), StoreError>;
+
+    fn get_event(&self, id: &Uuid) -> Result<Option<WichtelEvent>, StoreError>;
+
+    fn get_event_by_invite_code(&self, code: &str) -> Result<Option<WichtelEvent>, StoreError>;
+
+    /// Returns `false` if no event with this id exists.
+    fn update_event(
+        &self,
+        id: &Uuid,
+        f: &mut dyn FnMut(&mut WichtelEvent),
+    ) -> Result<bool, StoreError>;
+
+    /// Ids of events whose `pending_auto_close` is at or before `now`.
+    fn events_due_for_auto_close(&self, now: DateTime<Utc>) -> Result<Vec<Uuid>, StoreError>;
+}
@@ -0,0 +1,289 @@
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use uuid::Uuid;
+
+use super::{EventStore, StoreError};
+use crate::models::WichtelEvent;
+
+// Each entry is applied once, in order; the number of applied migrations is
+// tracked in SQLite's `user_version` pragma. Never edit a shipped migration,
+// append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE events (
+        id TEXT PRIMARY KEY NOT NULL,
+        invite_code TEXT NOT NULL UNIQUE,
+        data TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );",
+    // Unix timestamp of `WichtelEvent::pending_auto_close`, kept in its own
+    // column so the scheduler doesn't have to decode every event.
+    "ALTER TABLE events ADD COLUMN auto_close_at INTEGER;
+     CREATE INDEX events_auto_close_at ON events (auto_close_at)
+        WHERE auto_close_at IS NOT NULL;",
+];
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(mut conn: Connection) -> Result<Self, StoreError> {
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), StoreError> {
+    let current: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (version, sql) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+        log::info!("Applied database migration {}", version + 1);
+    }
+    Ok(())
+}
+
+fn auto_close_at(event: &WichtelEvent) -> Option<i64> {
+    event.pending_auto_close().map(|at| at.timestamp())
+}
+
+fn decode(data: String) -> Result<WichtelEvent, StoreError> {
+    Ok(serde_json::from_str(&data)?)
+}
+
+impl EventStore for SqliteStore {
+    fn insert_event(&self, event: &WichtelEvent) -> Result<(), StoreError> {
+        let data = serde_json::to_string(event)?;
+        let now = Utc::now().to_rfc3339();
+        self.conn.lock().execute(
+            "INSERT INTO events (id, invite_code, data, created_at, updated_at, auto_close_at)
+             VALUES (?1, ?2, ?3, ?4, ?4, ?5)",
+            params![
+                event.id.to_string(),
+                event.invite_code,
+                data,
+                now,
+                auto_close_at(event)
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_event(&self, id: &Uuid) -> Result<Option<WichtelEvent>, StoreError> {
+        let data: Option<String> = self
+            .conn
+            .lock()
+            .query_row(
+                "SELECT data FROM events WHERE id = ?1",
+                params![id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        data.map(decode).transpose()
+    }
+
+    fn get_event_by_invite_code(&self, code: &str) -> Result<Option<WichtelEvent>, StoreError> {
+        let data: Option<String> = self
+            .conn
+            .lock()
+            .query_row(
+                "SELECT data FROM events WHERE invite_code = ?1",
+                params![code],
+                |row| row.get(0),
+            )
+            .optional()?;
+        data.map(decode).transpose()
+    }
+
+    fn update_event(
+        &self,
+        id: &Uuid,
+        f: &mut dyn FnMut(&mut WichtelEvent),
+    ) -> Result<bool, StoreError> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+
+        let data: Option<String> = tx
+            .query_row(
+                "SELECT data FROM events WHERE id = ?1",
+                params![id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let mut event = match data {
+            Some(data) => decode(data)?,
+            None => return Ok(false),
+        };
+
+        f(&mut event);
+
+        tx.execute(
+            "UPDATE events SET invite_code = ?2, data = ?3, updated_at = ?4, auto_close_at = ?5
+             WHERE id = ?1",
+            params![
+                id.to_string(),
+                event.invite_code,
+                serde_json::to_string(&event)?,
+                Utc::now().to_rfc3339(),
+                auto_close_at(&event)
+            ],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+    fn events_due_for_auto_close(&self, now: DateTime<Utc>) -> Result<Vec<Uuid>, StoreError> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT id FROM events WHERE auto_close_at <= ?1")?;
+        let ids = stmt
+            .query_map(params![now.timestamp()], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ids
+            .iter()
+            .filter_map(|id| Uuid::parse_str(id).ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    fn store() -> SqliteStore {
+        SqliteStore::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn event_with(names: &[&str]) -> WichtelEvent {
+        let mut event = WichtelEvent::new("Office".to_string());
+        for name in names {
+            event.add_participant(name.to_string(), None);
+        }
+        event
+    }
+
+    // Events have no `PartialEq`, their JSON is what gets stored anyway.
+    fn assert_same(a: &WichtelEvent, b: &WichtelEvent) {
+        assert_eq!(
+            serde_json::to_value(a).unwrap(),
+            serde_json::to_value(b).unwrap()
+        );
+    }
+
+    #[test]
+    fn migrations_run_once() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        let indexes: usize = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'events_auto_close_at'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(indexes, 1);
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let store = store();
+
+        let mut pending = event_with(&["Anna", "Ben", "Clara"]);
+        pending.registration_deadline = Some(Utc::now() + Duration::days(3));
+        pending.publish_commitment().unwrap();
+        store.insert_event(&pending).unwrap();
+
+        let mut drawn = event_with(&["Anna", "Ben", "Clara", "Dora"]);
+        drawn.publish_commitment().unwrap();
+        drawn.close_and_assign().unwrap();
+        store.insert_event(&drawn).unwrap();
+
+        for event in [&pending, &drawn] {
+            assert_same(&store.get_event(&event.id).unwrap().unwrap(), event);
+            let by_code = store.get_event_by_invite_code(&event.invite_code).unwrap();
+            assert_same(&by_code.unwrap(), event);
+        }
+        let stored = store.get_event(&drawn.id).unwrap().unwrap();
+        assert!(stored.participants.values().all(|p| p.assigned_to.len() == 1));
+        assert!(stored.draw_record.unwrap().commitment_matches());
+
+        assert!(store.get_event(&Uuid::from_u128(1)).unwrap().is_none());
+        assert!(store.get_event_by_invite_code("NOPE").unwrap().is_none());
+        assert!(store.insert_event(&pending).is_err());
+    }
+
+    #[test]
+    fn updates_are_written_back() {
+        let store = store();
+        let event = event_with(&["Anna", "Ben"]);
+        store.insert_event(&event).unwrap();
+
+        let updated = store
+            .update_event(&event.id, &mut |e| {
+                e.add_participant("Clara".to_string(), None);
+                e.invite_code = "NEWCODE".to_string();
+            })
+            .unwrap();
+        assert!(updated);
+
+        let stored = store.get_event_by_invite_code("NEWCODE").unwrap().unwrap();
+        assert_eq!(stored.participants.len(), 3);
+        assert!(store.get_event_by_invite_code(&event.invite_code).unwrap().is_none());
+
+        let missing = store.update_event(&Uuid::from_u128(1), &mut |_| panic!("no event"));
+        assert!(!missing.unwrap());
+    }
+
+    #[test]
+    fn finds_events_due_for_auto_close() {
+        let store = store();
+        let now = Utc::now();
+
+        let mut soon = event_with(&["Anna", "Ben"]);
+        soon.registration_deadline = Some(now + Duration::hours(1));
+        let mut later = event_with(&["Anna", "Ben"]);
+        later.registration_deadline = Some(now + Duration::days(2));
+        let without_deadline = event_with(&["Anna", "Ben"]);
+        for event in [&soon, &later, &without_deadline] {
+            store.insert_event(event).unwrap();
+        }
+
+        assert!(store.events_due_for_auto_close(now).unwrap().is_empty());
+        assert_eq!(store.events_due_for_auto_close(now + Duration::hours(1)).unwrap(), [soon.id]);
+        let mut due = store.events_due_for_auto_close(now + Duration::days(3)).unwrap();
+        due.sort();
+        let mut expected = vec![soon.id, later.id];
+        expected.sort();
+        assert_eq!(due, expected);
+
+        // Once tried, the deadline is no longer due.
+        store
+            .update_event(&soon.id, &mut |e| {
+                e.auto_close(now + Duration::hours(1));
+            })
+            .unwrap();
+        assert_eq!(store.events_due_for_auto_close(now + Duration::days(3)).unwrap(), [later.id]);
+    }
+}
@@ -0,0 +1,566 @@
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+use crate::draw::{self, DrawError, DrawMode, HistoryConstraint};
+
+// Bump when anything that influences the drawn pairs changes, so old draws
+// stay verifiable against the algorithm they were made with.
+pub const DRAW_ALGORITHM: &str = "wichtel-draw-v1";
+
+fn sha256_hex(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            hasher.update(b"\n");
+        }
+        hasher.update(part.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+pub fn generate_seed() -> String {
+    let mut seed = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut seed);
+    hex::encode(seed)
+}
+
+// The RNG a draw is made with. Everything the draw does is derived from it,
+// so the same seed and input always give the same pairs.
+fn seeded_rng(seed: &str) -> ChaCha20Rng {
+    ChaCha20Rng::from_seed(Sha256::digest(seed.as_bytes()).into())
+}
+
+/// Everything besides the seed that determines the outcome of a draw.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrawInput {
+    pub event_id: Uuid,
+    pub mode: DrawMode,
+    pub gifts_per_person: usize,
+    /// Sorted, in the order the draw sees them.
+    pub participants: Vec<Uuid>,
+    pub forbidden: BTreeSet<(Uuid, Uuid)>,
+    pub history_constraint: HistoryConstraint,
+    /// Pairs of earlier years to avoid, most recent year first.
+    pub history: Vec<BTreeSet<(Uuid, Uuid)>>,
+}
+
+impl DrawInput {
+    pub fn new(
+        event_id: Uuid,
+        mode: DrawMode,
+        gifts_per_person: usize,
+        participants: &[Uuid],
+        is_forbidden: impl Fn(Uuid, Uuid) -> bool,
+    ) -> Self {
+        let mut participants = participants.to_vec();
+        participants.sort();
+        let forbidden = participants
+            .iter()
+            .flat_map(|&giver| participants.iter().map(move |&receiver| (giver, receiver)))
+            .filter(|&(giver, receiver)| giver != receiver && is_forbidden(giver, receiver))
+            .collect();
+        Self {
+            event_id,
+            mode,
+            gifts_per_person,
+            participants,
+            forbidden,
+            history_constraint: HistoryConstraint::default(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Adds pairs of earlier years to avoid, most recent first. Pairs with
+    /// someone not taking part now are left out.
+    pub fn with_history<'a>(
+        mut self,
+        constraint: HistoryConstraint,
+        years: impl IntoIterator<Item = &'a [(Uuid, Uuid)]>,
+    ) -> Self {
+        let taking_part = |id: &Uuid| self.participants.binary_search(id).is_ok();
+        let history: Vec<BTreeSet<(Uuid, Uuid)>> = years
+            .into_iter()
+            .map(|pairs| {
+                pairs
+                    .iter()
+                    .filter(|(giver, receiver)| taking_part(giver) && taking_part(receiver))
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        self.history_constraint = constraint;
+        self.history = history;
+        self
+    }
+
+    /// Text form that the commitment is computed over, e.g.
+    ///
+    /// ```text
+    /// wichtel-draw-v1
+    /// event:<id>
+    /// mode:any_derangement
+    /// gifts:2
+    /// participants:<id>,<id>,...
+    /// forbidden:<giver>><receiver>,...
+    /// history:soft
+    /// avoid:<giver>><receiver>,...
+    /// ```
+    ///
+    /// The `gifts` line is left out for one gift per person and the history
+    /// lines without earlier years, so older draws keep their commitments.
+    pub fn canonical(&self) -> String {
+        let participants: Vec<String> = self.participants.iter().map(Uuid::to_string).collect();
+        let gifts = match self.gifts_per_person {
+            1 => String::new(),
+            gifts => format!("\ngifts:{}", gifts),
+        };
+        let mut history = String::new();
+        if !self.history.is_empty() {
+            history.push_str(match self.history_constraint {
+                HistoryConstraint::Soft => "\nhistory:soft",
+                HistoryConstraint::Hard => "\nhistory:hard",
+            });
+            for year in &self.history {
+                history.push_str("\navoid:");
+                history.push_str(&pair_list(year));
+            }
+        }
+        format!(
+            "{}\nevent:{}\nmode:{}{}\nparticipants:{}\nforbidden:{}{}",
+            DRAW_ALGORITHM,
+            self.event_id,
+            mode_name(self.mode),
+            gifts,
+            participants.join(","),
+            pair_list(&self.forbidden),
+            history
+        )
+    }
+
+    pub fn parse(canonical: &str) -> Option<Self> {
+        let mut lines = canonical.lines().peekable();
+        if lines.next()? != DRAW_ALGORITHM {
+            return None;
+        }
+        let mut field = |name: &str, optional: bool| {
+            let value = lines.peek()?.strip_prefix(name)?.strip_prefix(':');
+            if value.is_some() || !optional {
+                lines.next();
+            }
+            value
+        };
+        let event_id = Uuid::parse_str(field("event", false)?).ok()?;
+        let mode = match field("mode", false)? {
+            "any_derangement" => DrawMode::AnyDerangement,
+            "single_cycle" => DrawMode::SingleCycle,
+            _ => return None,
+        };
+        let gifts_per_person = match field("gifts", true) {
+            Some(gifts) => gifts.parse().ok()?,
+            None => 1,
+        };
+        let participants = split_list(field("participants", false)?)
+            .map(|id| Uuid::parse_str(id).ok())
+            .collect::<Option<_>>()?;
+        let forbidden = parse_pair_list(field("forbidden", false)?)?;
+        let history_constraint = match field("history", true) {
+            None | Some("soft") => HistoryConstraint::Soft,
+            Some("hard") => HistoryConstraint::Hard,
+            Some(_) => return None,
+        };
+        let mut history = Vec::new();
+        while let Some(year) = field("avoid", true) {
+            history.push(parse_pair_list(year)?);
+        }
+        Some(Self {
+            event_id,
+            mode,
+            gifts_per_person,
+            participants,
+            forbidden,
+            history_constraint,
+            history,
+        })
+    }
+
+    /// Runs the draw for `seed`. Always gives the same pairs for the same seed.
+    ///
+    /// Under a soft history constraint, the oldest year still avoided is
+    /// dropped whenever no draw avoids all of them.
+    pub fn draw(&self, seed: &str) -> Result<Vec<(Uuid, Uuid)>, DrawError> {
+        let mut rng = seeded_rng(seed);
+        let mut avoided_years = self.history.len();
+        loop {
+            let avoided = &self.history[..avoided_years];
+            let result = draw::generate_multi_assignments(
+                &self.participants,
+                self.mode,
+                self.gifts_per_person,
+                |giver, receiver| {
+                    self.forbidden.contains(&(giver, receiver))
+                        || avoided.iter().any(|year| year.contains(&(giver, receiver)))
+                },
+                &mut rng,
+            );
+            match result {
+                Err(_) if avoided_years > 0 && self.history_constraint == HistoryConstraint::Soft => {
+                    avoided_years -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn mode_name(mode: DrawMode) -> &'static str {
+    match mode {
+        DrawMode::AnyDerangement => "any_derangement",
+        DrawMode::SingleCycle => "single_cycle",
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').filter(|item| !item.is_empty())
+}
+
+fn pair_list(pairs: &BTreeSet<(Uuid, Uuid)>) -> String {
+    pairs
+        .iter()
+        .map(|(giver, receiver)| format!("{}>{}", giver, receiver))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_pair_list(list: &str) -> Option<BTreeSet<(Uuid, Uuid)>> {
+    split_list(list)
+        .map(|pair| {
+            let (giver, receiver) = pair.split_once('>')?;
+            Some((Uuid::parse_str(giver).ok()?, Uuid::parse_str(receiver).ok()?))
+        })
+        .collect()
+}
+
+pub fn commitment(seed: &str, input: &str) -> String {
+    sha256_hex(&[seed, input])
+}
+
+// Secret salt of a giver's leaf. Derived from the seed so the whole tree can
+// be rebuilt once the seed is revealed, but unguessable before that.
+pub fn pair_salt(seed: &str, giver: Uuid) -> String {
+    sha256_hex(&["salt", seed, &giver.to_string()])
+}
+
+pub fn pair_leaf(salt: &str, giver: Uuid, receiver: Uuid) -> String {
+    sha256_hex(&[salt, &format!("{}>{}", giver, receiver)])
+}
+
+fn hash_nodes(left: &str, right: &str) -> String {
+    sha256_hex(&[left, right])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// A sibling hash on the way from a leaf to the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub side: Side,
+    pub hash: String,
+}
+
+// All levels of the Merkle tree, leaves first. An odd node at the end of a
+// level is carried up unchanged.
+fn merkle_levels(leaves: Vec<String>) -> Vec<Vec<String>> {
+    let mut levels = vec![leaves];
+    while levels.last().is_some_and(|level| level.len() > 1) {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash_nodes(left, right),
+                [single] => single.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// Merkle root over the pair leaves, ordered by giver id.
+pub fn merkle_root(leaves: Vec<String>) -> String {
+    merkle_levels(leaves)
+        .last()
+        .and_then(|level| level.first().cloned())
+        .unwrap_or_default()
+}
+
+pub fn merkle_proof(leaves: Vec<String>, mut index: usize) -> Vec<ProofStep> {
+    let levels = merkle_levels(leaves);
+    let mut proof = Vec::new();
+    for level in &levels[..levels.len() - 1] {
+        let sibling = index ^ 1;
+        if let Some(hash) = level.get(sibling) {
+            proof.push(ProofStep {
+                side: if sibling < index { Side::Left } else { Side::Right },
+                hash: hash.clone(),
+            });
+        }
+        index /= 2;
+    }
+    proof
+}
+
+pub fn verify_proof(leaf: &str, proof: &[ProofStep], root: &str) -> bool {
+    let computed = proof.iter().fold(leaf.to_string(), |node, step| match step.side {
+        Side::Left => hash_nodes(&step.hash, &node),
+        Side::Right => hash_nodes(&node, &step.hash),
+    });
+    computed == root
+}
+
+/// Leaves of all drawn pairs in tree order.
+pub fn pair_leaves(seed: &str, pairs: &[(Uuid, Uuid)]) -> Vec<String> {
+    let mut pairs = pairs.to_vec();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|&(giver, receiver)| pair_leaf(&pair_salt(seed, giver), giver, receiver))
+        .collect()
+}
+
+/// A draw announced in advance. The seed stays secret until it is revealed
+/// after the draw.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrawCommitment {
+    pub commitment: String,
+    pub seed: String,
+    pub published_at: DateTime<Utc>,
+}
+
+impl DrawCommitment {
+    pub fn new(seed: String, input: &DrawInput) -> Self {
+        Self {
+            commitment: commitment(&seed, &input.canonical()),
+            seed,
+            published_at: Utc::now(),
+        }
+    }
+}
+
+/// A commitment that was given up without being used, kept so everyone can
+/// see how often the organizer started over.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawnCommitment {
+    pub commitment: String,
+    pub published_at: DateTime<Utc>,
+    pub withdrawn_at: DateTime<Utc>,
+}
+
+/// Everything needed to check a draw afterwards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrawRecord {
+    pub commitment: String,
+    /// When the commitment was published, `None` if it was only made at the
+    /// draw itself.
+    pub committed_at: Option<DateTime<Utc>>,
+    pub seed: String,
+    pub seed_revealed_at: Option<DateTime<Utc>>,
+    /// Canonical `DrawInput` the pairs were drawn from.
+    pub input: String,
+    pub pairs_root: String,
+    pub drawn_at: DateTime<Utc>,
+    /// Set once assignments were changed by hand after the draw, e.g. when
+    /// someone dropped out or joined late.
+    pub amended_at: Option<DateTime<Utc>>,
+}
+
+/// What a participant needs to check their own pair against the published
+/// root without learning anything about the other pairs.
+#[derive(Debug, Clone, Serialize)]
+pub struct PairProof {
+    pub giver: Uuid,
+    pub receiver: Uuid,
+    pub salt: String,
+    pub leaf: String,
+    pub proof: Vec<ProofStep>,
+    pub valid: bool,
+}
+
+impl DrawRecord {
+    pub fn new(
+        commitment: String,
+        committed_at: Option<DateTime<Utc>>,
+        seed: String,
+        input: &DrawInput,
+        pairs: &[(Uuid, Uuid)],
+    ) -> Self {
+        let pairs_root = merkle_root(pair_leaves(&seed, pairs));
+        Self {
+            commitment,
+            committed_at,
+            input: input.canonical(),
+            seed,
+            seed_revealed_at: None,
+            pairs_root,
+            drawn_at: Utc::now(),
+            amended_at: None,
+        }
+    }
+
+    /// The pairs as originally drawn, recomputed from the seed.
+    pub fn replay(&self) -> Option<Vec<(Uuid, Uuid)>> {
+        DrawInput::parse(&self.input)?.draw(&self.seed).ok()
+    }
+
+    pub fn commitment_matches(&self) -> bool {
+        commitment(&self.seed, &self.input) == self.commitment
+    }
+
+    pub fn root_matches(&self) -> bool {
+        self.replay()
+            .is_some_and(|pairs| merkle_root(pair_leaves(&self.seed, &pairs)) == self.pairs_root)
+    }
+
+    /// Proofs for the pairs `giver` drew, empty if they were not part of the
+    /// draw.
+    pub fn pair_proofs(&self, giver: Uuid) -> Vec<PairProof> {
+        let Some(mut pairs) = self.replay() else {
+            return Vec::new();
+        };
+        pairs.sort();
+        let leaves = pair_leaves(&self.seed, &pairs);
+        let salt = pair_salt(&self.seed, giver);
+        pairs
+            .iter()
+            .enumerate()
+            .filter(|(_, &(g, _))| g == giver)
+            .map(|(index, &(_, receiver))| {
+                let leaf = pair_leaf(&salt, giver, receiver);
+                let proof = merkle_proof(leaves.clone(), index);
+                let valid = verify_proof(&leaf, &proof, &self.pairs_root);
+                PairProof {
+                    giver,
+                    receiver,
+                    salt: salt.clone(),
+                    leaf,
+                    proof,
+                    valid,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(n: usize) -> Vec<Uuid> {
+        (1..=n as u128).map(Uuid::from_u128).collect()
+    }
+
+    fn sample_input(gifts_per_person: usize) -> DrawInput {
+        let participants = ids(6);
+        let (a, b) = (participants[0], participants[1]);
+        DrawInput::new(
+            Uuid::from_u128(99),
+            DrawMode::AnyDerangement,
+            gifts_per_person,
+            &participants,
+            |giver, receiver| (giver, receiver) == (a, b) || (giver, receiver) == (b, a),
+        )
+        .with_history(
+            HistoryConstraint::Hard,
+            [&[(participants[2], participants[3])][..]],
+        )
+    }
+
+    #[test]
+    fn canonical_round_trip() {
+        for gifts in [1, 2] {
+            let input = sample_input(gifts);
+            let canonical = input.canonical();
+            assert_eq!(DrawInput::parse(&canonical), Some(input));
+            assert_eq!(canonical.contains("\ngifts:"), gifts > 1);
+        }
+        assert_eq!(DrawInput::parse("wichtel-draw-v0\nevent:x"), None);
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        for gifts in [1, 2] {
+            let input = sample_input(gifts);
+            let seed = "0123456789abcdef";
+            let first = input.draw(seed).unwrap();
+            assert_eq!(first.len(), 6 * gifts);
+            assert_eq!(input.draw(seed), Ok(first.clone()));
+            assert_eq!(DrawInput::parse(&input.canonical()).unwrap().draw(seed), Ok(first));
+        }
+    }
+
+    #[test]
+    fn proofs_verify_and_tampering_is_rejected() {
+        let seed = "seed";
+        let input = sample_input(2);
+        let mut pairs = input.draw(seed).unwrap();
+        pairs.sort();
+        let leaves = pair_leaves(seed, &pairs);
+        let root = merkle_root(leaves.clone());
+
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = merkle_proof(leaves.clone(), index);
+            assert!(verify_proof(leaf, &proof, &root));
+
+            let (giver, receiver) = pairs[index];
+            let other = pairs.iter().find(|&&(g, r)| g == giver && r != receiver).unwrap().1;
+            let forged = pair_leaf(&pair_salt(seed, giver), giver, other);
+            assert!(!verify_proof(&forged, &proof, &root));
+
+            let mut tampered = proof.clone();
+            tampered[0].hash = sha256_hex(&["tampered"]);
+            assert!(!verify_proof(leaf, &tampered, &root));
+        }
+    }
+
+    #[test]
+    fn record_checks_detect_mismatches() {
+        let input = sample_input(1);
+        let seed = generate_seed();
+        let pairs = input.draw(&seed).unwrap();
+        let record = DrawRecord::new(
+            commitment(&seed, &input.canonical()),
+            None,
+            seed.clone(),
+            &input,
+            &pairs,
+        );
+        assert!(record.commitment_matches());
+        assert!(record.root_matches());
+        assert_eq!(record.replay(), Some(pairs));
+
+        let other_seed = DrawRecord {
+            seed: generate_seed(),
+            ..record.clone()
+        };
+        assert!(!other_seed.commitment_matches());
+
+        let other_input = DrawRecord {
+            input: sample_input(2).canonical(),
+            ..record.clone()
+        };
+        assert!(!other_input.commitment_matches());
+        assert!(!other_input.root_matches());
+    }
+}